@@ -0,0 +1,52 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use json_template::*;
+
+fn template() -> serde_json::Value {
+    serde_json::json!({
+        "name": "{user.name}",
+        "greeting": "Hello, {user.name}! You are {user.age} years old.",
+        "email": "{user.email ?? unknown}",
+        "first_tag": "{user.tags[0] | upper}",
+        "servers": [
+            { "host": "{servers.0.host}", "port": "{servers.0.port}" },
+            { "host": "{servers.1.host}", "port": "{servers.1.port}" }
+        ],
+        "urls": { "$for": "server in {servers}", "$do": "http://{server.host}:{server.port}" },
+        "static": { "version": 1, "description": "A value without placeholders." }
+    })
+}
+
+fn context(index: usize) -> Context {
+    Context::new().with_data(serde_json::json!({
+        "user": { "name": format!("user-{}", index), "age": index, "tags": ["admin", "dev"] },
+        "servers": [
+            { "host": "localhost", "port": 8000 + index },
+            { "host": "example.com", "port": 9000 + index }
+        ]
+    }))
+}
+
+fn render(criterion: &mut Criterion) {
+    let value = template();
+    let contexts = (0 .. 100).map(context).collect::<Vec<_>>();
+    let mut group = criterion.benchmark_group("render 100 contexts");
+    group.bench_function("deserialize_with_context", |bencher| {
+        bencher.iter(|| {
+            for context in &contexts {
+                let _: serde_json::Value = Deserializer::new().deserialize_with_context(value.clone(), context).unwrap();
+            }
+        })
+    });
+    let template = Template::compile(value.clone()).unwrap();
+    group.bench_function("Template::render", |bencher| {
+        bencher.iter(|| {
+            for context in &contexts {
+                template.render(context).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
@@ -0,0 +1,201 @@
+//! Analysis module.
+
+use std::borrow::Cow;
+
+use serde_json::{Map, Value};
+
+use crate::{get_segment, has_filter, has_function, locate, Builtin, Context, Deserializer, Error, Expression, Fallback, Loop, Node, Origin, Part, Path, Placeholder, Result, Segment, Syntax};
+
+/// A placeholder found in a template.
+#[derive(Debug)]
+pub struct PlaceholderReport {
+    /// JSON pointer of the string containing the placeholder.
+    pub pointer: String,
+    /// The placeholder, with its function `type_` if any.
+    pub placeholder: Placeholder,
+    /// Why the placeholder can't be resolved, if it can't.
+    pub error: Option<Error>
+}
+
+impl PlaceholderReport {
+    /// Whether the placeholder can be resolved.
+    pub fn is_resolved(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Every placeholder of a template and whether it can be resolved.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// Placeholders, in document order.
+    pub placeholders: Vec<PlaceholderReport>,
+    /// Malformed strings and directives, with their JSON pointer.
+    pub malformed: Vec<(String, Error)>
+}
+
+impl Analysis {
+    /// Whether every placeholder can be resolved and nothing is malformed.
+    pub fn is_valid(&self) -> bool {
+        self.malformed.is_empty() && self.placeholders.iter().all(PlaceholderReport::is_resolved)
+    }
+
+    /// Placeholders that can't be resolved.
+    pub fn unresolved(&self) -> impl Iterator<Item = &PlaceholderReport> {
+        self.placeholders.iter().filter(|report| !report.is_resolved())
+    }
+
+    /// Analyze the value at `pointer` of the document being resolved in `context`, skipping the paths of loop `variables`.
+    pub(crate) fn collect(&mut self, deserializer: &Deserializer, value: &Value, pointer: &str, context: &Context, variables: &[&str]) {
+        match value {
+            Value::String(string) => match Node::string(string, deserializer.syntax(), Origin::Template) {
+                Ok(node) => {
+                    for expression in expressions(&node) {
+                        let error = check(expression, context, variables).err();
+                        self.placeholders.push(PlaceholderReport { pointer: pointer.to_string(), placeholder: expression.placeholder.clone(), error });
+                    }
+                },
+                Err(error) => self.malformed.push((pointer.to_string(), error))
+            },
+            Value::Array(array) => {
+                for (index, value) in array.iter().enumerate() {
+                    self.collect(deserializer, value, &Path::pointer(pointer, &index.to_string()), context, variables);
+                }
+            },
+            Value::Object(object) if object.contains_key("$for") => self.collect_loop(deserializer, object, pointer, context, variables),
+            Value::Object(object) => {
+                if object.contains_key("$if") {
+                    if let Err(error) = Node::check_conditional(object) {
+                        self.malformed.push((pointer.to_string(), error));
+                    }
+                }
+                for (key, value) in object {
+                    self.collect(deserializer, value, &Path::pointer(pointer, key), context, variables);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Analyze a `$for` loop, skipping the paths of its variable in `$do`.
+    fn collect_loop(&mut self, deserializer: &Deserializer, object: &Map<String, Value>, pointer: &str, context: &Context, variables: &[&str]) {
+        for (key, value) in object.iter().filter(|(key, _)| *key != "$do") {
+            self.collect(deserializer, value, &Path::pointer(pointer, key), context, variables);
+        }
+        let variable = match Loop::split(object) {
+            Ok((_, variable, _)) => Some(variable),
+            Err(error) => {
+                // Malformed strings are already reported.
+                let for_pointer = Path::pointer(pointer, "$for");
+                if !self.malformed.iter().any(|(pointer, _)| *pointer == for_pointer) {
+                    self.malformed.push((for_pointer, error));
+                }
+                None
+            }
+        };
+        if let Some(body) = object.get("$do") {
+            let variables = [variables, variable.as_slice()].concat();
+            self.collect(deserializer, body, &Path::pointer(pointer, "$do"), context, &variables);
+        }
+    }
+}
+
+/// Get the placeholders of a compiled string.
+fn expressions(node: &Node) -> Vec<&Expression> {
+    match node {
+        Node::Placeholder(expression) => vec![expression],
+        Node::Text { parts, .. } => parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Placeholder(expression) => Some(expression.as_ref()),
+                Part::Text(_) => None
+            })
+            .collect(),
+        _ => Vec::new()
+    }
+}
+
+/// Check whether a placeholder can be resolved without resolving it.
+/// Functions aren't called: they only need to be registered, and the files they include to be found.
+fn check(expression: &Expression, context: &Context, variables: &[&str]) -> Result<()> {
+    let placeholder = &expression.placeholder;
+    let result = match placeholder.type_.as_deref() {
+        Some(type_) => check_function(type_, expression, context, variables),
+        None => check_path(expression, context, variables)
+    };
+    match (result, &expression.fallback) {
+        (Err(error), Some(fallback)) if error.is_not_found() => match fallback {
+            Fallback::Literal(_) => {},
+            Fallback::Template(node) => {
+                for expression in expressions(node) {
+                    check(expression, context, variables)?;
+                }
+            }
+        },
+        (result, _) => result?
+    }
+    match expression.filters.iter().find(|filter| !has_filter(context, filter)) {
+        Some(filter) => Err(Error::FunctionNotFound { name: filter.clone() }),
+        None => Ok(())
+    }
+}
+
+/// Check that a function is registered and, for includes, that the file can be found.
+fn check_function(type_: &str, expression: &Expression, context: &Context, variables: &[&str]) -> Result<()> {
+    if !has_function(context, type_) {
+        return Err(Error::FunctionNotFound { name: type_.to_string() })
+    }
+    let syntax = expression.placeholder.syntax();
+    let placeholders = syntax.placeholders(&expression.path)?;
+    if placeholders.is_empty() && matches!(context.functions().builtin(type_), Some(Builtin::Include(_))) {
+        return locate(context, &expression.path).map(drop)
+    }
+    // Arguments with placeholders are only known when rendering, but their placeholders must resolve.
+    for placeholder in placeholders {
+        check(&Expression::new(placeholder, Origin::Template)?, context, variables)?;
+    }
+    Ok(())
+}
+
+/// Check that a path can be found in the context data or in the document, without resolving what's found.
+/// Paths starting with a loop variable or a `$` variable are only known when rendering.
+fn check_path(expression: &Expression, context: &Context, variables: &[&str]) -> Result<()> {
+    for segment in &expression.segments {
+        if let Segment::Placeholder(expression) = segment {
+            check(expression, context, variables)?;
+        }
+    }
+    match expression.segments.first() {
+        Some(Segment::Key(key)) if key.starts_with('$') || variables.contains(&key.as_str()) => return Ok(()),
+        Some(Segment::Placeholder(_)) | None => return Ok(()),
+        Some(Segment::Key(_)) => {}
+    }
+    if [context.data(), context.current_data()].into_iter().any(|value| lookup(value, &expression.segments, expression.placeholder.syntax())) {
+        Ok(())
+    } else {
+        Err(Error::PathNotFound { path: expression.path.clone(), template_location: None })
+    }
+}
+
+/// Whether path segments can be found in `value`.
+/// Lookups stop at templated strings, directives and placeholders, whose value is only known when rendering.
+fn lookup(value: &Value, segments: &[Segment], syntax: Syntax) -> bool {
+    let mut value = Cow::Borrowed(value);
+    for segment in segments {
+        let Segment::Key(key) = segment else { return true };
+        let found = match value {
+            Cow::Borrowed(value) => get_segment(value, key),
+            Cow::Owned(value) => get_segment(&value, key).map(|value| Cow::Owned(value.into_owned()))
+        };
+        let Some(found) = found else { return false };
+        let templated = match found.as_ref() {
+            Value::String(string) => syntax.placeholders(string).map_or(true, |placeholders| !placeholders.is_empty()),
+            Value::Object(object) => Deserializer::is_directive(object),
+            _ => false
+        };
+        if templated {
+            return true
+        }
+        value = found;
+    }
+    true
+}
@@ -0,0 +1,83 @@
+//! Renders a JSON template from the command line.
+
+use std::{io::Write, path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use json_template::{Context, Deserializer, Error, Result};
+use serde_json::Value;
+
+/// Render a JSON template.
+#[derive(Parser)]
+#[command(name = "json-template", version, about)]
+struct Arguments {
+    /// Template file.
+    template: PathBuf,
+    /// Data files, merged in order. They can be templates too.
+    data: Vec<PathBuf>,
+    /// Set a data value, like `--set server.port=8080`. Values are parsed as JSON, or used as strings if they aren't valid JSON.
+    #[arg(long = "set", value_name = "PATH=VALUE")]
+    values: Vec<String>,
+    /// Base directory of relative includes, instead of the directory of the file including them.
+    #[arg(long = "dir", value_name = "DIRECTORY")]
+    directory: Option<PathBuf>,
+    /// Directory searched for included files not found in the base directory. Can be repeated.
+    #[arg(long = "include-dir", value_name = "DIRECTORY")]
+    include_directories: Vec<PathBuf>,
+    /// Accept comments and trailing commas in JSON files.
+    #[arg(long)]
+    lenient: bool,
+    /// Pretty-print the output.
+    #[arg(long)]
+    pretty: bool,
+    /// Write the output to a file instead of stdout.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>
+}
+
+/// Parse `a.b=value` into `{"a": {"b": value}}`.
+fn parse_value(assignment: &str) -> Result<Value> {
+    let (path, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| Error::custom(format!("Expected PATH=VALUE, found {:?}", assignment)))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    let value = path
+        .rsplit('.')
+        .fold(value, |value, key| Value::Object([(key.to_string(), value)].into_iter().collect()));
+    Ok(value)
+}
+
+fn run(arguments: Arguments) -> Result<()> {
+    let deserializer = Deserializer::new().with_lenient(arguments.lenient);
+    let mut context = Context::new();
+    context
+        .set_fixed_directory(arguments.directory.is_some())
+        .set_directory(arguments.directory)
+        .set_include_directories(arguments.include_directories);
+    for path in arguments.data {
+        let data = deserializer.render(path, &context)?;
+        context.override_data(data);
+    }
+    for assignment in &arguments.values {
+        context.override_data(parse_value(assignment)?);
+    }
+    // Render before opening the output, so it's left untouched on errors.
+    let mut text = Vec::new();
+    deserializer.render_to_writer(arguments.template, &context, &mut text, arguments.pretty)?;
+    text.push(b'\n');
+    match arguments.output {
+        Some(path) => std::fs::write(&path, text).map_err(|source| Error::Io { path, source }),
+        None => std::io::stdout()
+            .write_all(&text)
+            .map_err(|source| Error::Io { path: PathBuf::from("<stdout>"), source })
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Arguments::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! File cache module.
+
+use std::{collections::{BTreeSet, HashMap}, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard, PoisonError}};
+
+use serde_json::Value;
+
+use crate::{absolute, FileSystem, Format, Result, Syntax, Template};
+
+/// Cache of the files included with `{file:...}`, keyed by canonical path.
+/// Parsed files are always cached. Rendered files are only cached if enabled, because they depend on the context data.
+#[derive(Default)]
+pub struct FileCache {
+    /// Whether rendered files are cached.
+    render_cache: bool,
+    /// Parsed and compiled files.
+    parsed: Mutex<HashMap<PathBuf, (Variant, Arc<Template>)>>,
+    /// Rendered files.
+    rendered: Mutex<HashMap<PathBuf, (Variant, Value)>>,
+    /// File system the cached files were read from, canonicalizing the paths looked up.
+    file_system: Mutex<Option<Arc<dyn FileSystem>>>
+}
+
+/// Format and placeholder syntax a file was cached with.
+type Variant = (Format, Syntax);
+
+/// Lock a cache map, ignoring poisoning since entries are only inserted whole.
+fn lock<T>(map: &Mutex<T>) -> MutexGuard<'_, T> {
+    map.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl FileCache {
+    /// Create a new file cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether rendered files are cached. They're reused until removed, even if the context data changes.
+    pub fn with_render_cache(mut self, render_cache: bool) -> Self {
+        self.render_cache = render_cache;
+        self
+    }
+
+    /// Set whether rendered files are cached. They're reused until removed, even if the context data changes.
+    pub fn set_render_cache(&mut self, render_cache: bool) -> &mut Self {
+        self.render_cache = render_cache;
+        self
+    }
+
+    /// Get whether rendered files are cached.
+    pub fn render_cache(&self) -> bool {
+        self.render_cache
+    }
+
+    /// Canonicalize a path through the file system the cached files were read from.
+    /// Paths that don't exist are only made absolute.
+    fn canonical(&self, path: &Path) -> PathBuf {
+        let file_system = lock(&self.file_system).clone();
+        file_system
+            .and_then(|file_system| file_system.canonicalize(path).ok())
+            .unwrap_or_else(|| absolute(path))
+    }
+
+    /// Get the parsed file at `path`, if cached.
+    pub fn parsed(&self, path: impl AsRef<Path>) -> Option<Value> {
+        lock(&self.parsed)
+            .get(&self.canonical(path.as_ref()))
+            .map(|(_, template)| template.source().as_ref().clone())
+    }
+
+    /// Get the rendered file at `path`, if cached.
+    pub fn rendered(&self, path: impl AsRef<Path>) -> Option<Value> {
+        lock(&self.rendered)
+            .get(&self.canonical(path.as_ref()))
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Get the canonical paths of the cached files, sorted.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = lock(&self.parsed).keys().cloned().collect::<BTreeSet<_>>();
+        paths.extend(lock(&self.rendered).keys().cloned());
+        paths.into_iter().collect()
+    }
+
+    /// Remove the file at `path` from the cache, returning whether it was cached.
+    pub fn remove(&self, path: impl AsRef<Path>) -> bool {
+        let path = self.canonical(path.as_ref());
+        let parsed = lock(&self.parsed).remove(&path).is_some();
+        let rendered = lock(&self.rendered).remove(&path).is_some();
+        parsed || rendered
+    }
+
+    /// Remove every file from the cache.
+    pub fn clear(&self) {
+        lock(&self.parsed).clear();
+        lock(&self.rendered).clear();
+    }
+
+    /// Get the compiled file at `path`, canonical in `file_system`, loading it if it isn't cached in this format and syntax.
+    pub(crate) fn template(&self, path: &Path, file_system: Arc<dyn FileSystem>, format: Format, syntax: Syntax, load: impl FnOnce() -> Result<Template>) -> Result<Arc<Template>> {
+        *lock(&self.file_system) = Some(file_system);
+        if let Some((_, template)) = lock(&self.parsed).get(path).filter(|(cached, _)| *cached == (format, syntax)) {
+            return Ok(template.clone())
+        }
+        let template = Arc::new(load()?);
+        lock(&self.parsed).insert(path.to_path_buf(), ((format, syntax), template.clone()));
+        Ok(template)
+    }
+
+    /// Get the rendered file at the canonical `path`, rendering it if it isn't cached in this format and syntax or if rendered files aren't cached.
+    pub(crate) fn render(&self, path: &Path, format: Format, syntax: Syntax, render: impl FnOnce() -> Result<Value>) -> Result<Value> {
+        if !self.render_cache {
+            return render()
+        }
+        if let Some((_, value)) = lock(&self.rendered).get(path).filter(|(cached, _)| *cached == (format, syntax)) {
+            return Ok(value.clone())
+        }
+        let value = render()?;
+        lock(&self.rendered).insert(path.to_path_buf(), ((format, syntax), value.clone()));
+        Ok(value)
+    }
+}
@@ -1,22 +1,111 @@
 //! Context module.
 
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf, sync::{Arc, Mutex, PoisonError}};
 
 use serde_json::Value;
 
-use crate::{Deserializer, Functions, GetDot, Placeholder, JSON};
+use crate::{Deserializer, Error, FileCache, FileSystem, Functions, GetDot, Origin, Placeholder, OsFileSystem, Result, Sandbox, Segment, JSON};
+
+/// An immutable stack. Pushing creates a new stack sharing the entries of the previous one.
+struct Stack<T>(Option<Arc<Entry<T>>>);
+
+/// An entry of a stack.
+struct Entry<T> {
+    value: T,
+    next: Stack<T>,
+    len: usize
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<T> Clone for Stack<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Stack<T> {
+    fn push(&self, value: T) -> Self {
+        Self(Some(Arc::new(Entry { value, next: self.clone(), len: self.len() + 1 })))
+    }
+
+    fn len(&self) -> usize {
+        self.0.as_ref().map_or(0, |entry| entry.len)
+    }
+
+    /// Iterate from the last pushed entry to the first.
+    fn iter(&self) -> impl Iterator<Item = &T> {
+        std::iter::successors(self.0.as_deref(), |entry| entry.next.0.as_deref()).map(|entry| &entry.value)
+    }
+
+    /// Get the stack as it was when it had `len` entries.
+    fn truncate(&self, len: usize) -> Self {
+        let mut stack = self.clone();
+        while stack.len() > len {
+            stack = stack.0.as_ref().map(|entry| entry.next.clone()).unwrap_or_default();
+        }
+        stack
+    }
+
+    /// Get the entries from the first pushed to the last.
+    fn to_vec(&self) -> Vec<&T> {
+        let mut entries = self.iter().collect::<Vec<_>>();
+        entries.reverse();
+        entries
+    }
+}
+
+/// Resolved nodes of the document being resolved, keyed by their JSON pointer.
+#[derive(Default)]
+struct Memo {
+    /// Number of scopes when the document started resolving. Nodes are resolved without the scopes pushed after.
+    depth: usize,
+    /// Resolved values.
+    values: Mutex<HashMap<String, Option<Value>>>
+}
+
+/// State of a render. Nested resolutions get their own copy, so renders sharing a context don't interfere.
+#[derive(Default, Clone)]
+struct Frame {
+    /// JSON data being resolved.
+    current: Arc<Value>,
+    /// Placeholders being resolved in the current document.
+    resolving: Stack<String>,
+    /// Files being included.
+    including: Stack<PathBuf>,
+    /// Scoped variables, like loop bindings, innermost first.
+    scopes: Stack<Value>,
+    /// Nodes of the current document already resolved in this render.
+    resolved: Arc<Memo>
+}
 
 /// Deserialization context.
 #[derive(Default, Clone)]
 pub struct Context {
     /// JSON data.
-    data: Value,
+    data: Arc<Value>,
     /// Directory.
-    directory: Option<PathBuf>,
+    directory: Option<Arc<PathBuf>>,
+    /// File system files are read from. The operating system file system is used if not set.
+    file_system: Option<Arc<dyn FileSystem>>,
+    /// Directories searched in order for included files not found in the directory.
+    include_directories: Arc<[PathBuf]>,
+    /// Directories included files are confined to.
+    sandbox: Option<Arc<Sandbox>>,
+    /// Whether included files resolve their includes relative to the context directory instead of their own.
+    fixed_directory: bool,
     /// Functions.
-    functions: Functions,
-    /// JSON data being resolved.
-    current: Value
+    functions: Arc<Functions>,
+    /// Environment variables. The process environment is used if not set.
+    environment: Option<Arc<HashMap<String, String>>>,
+    /// Cache of included files.
+    file_cache: Option<Arc<FileCache>>,
+    /// State of the render using the context.
+    frame: Frame
 }
 
 impl Context {
@@ -27,13 +116,13 @@ impl Context {
 
     /// Set data.
     pub fn with_data(mut self, data: Value) -> Self {
-        self.data = data.into();
+        self.data = Arc::new(data);
         self
     }
 
     /// Set data.
     pub fn set_data(&mut self, data: Value) -> &mut Self {
-        self.data = data.into();
+        self.data = Arc::new(data);
         self
     }
 
@@ -43,17 +132,29 @@ impl Context {
     }
 
     /// Set function.
-    pub fn set_function(&mut self, name: impl AsRef<str>, function: impl Fn(&Deserializer, &Context, &Placeholder) -> serde_json::Result<Value> + 'static) -> &mut Self {
-        self.functions.register(name, function);
+    pub fn set_function(&mut self, name: impl AsRef<str>, function: impl Fn(&Deserializer, &Context, &Placeholder) -> Result<Value> + Send + Sync + 'static) -> &mut Self {
+        Arc::make_mut(&mut self.functions).register(name, function);
         self
     }
 
     /// Set function.
-    pub fn with_function(mut self, name: impl AsRef<str>, function: impl Fn(&Deserializer, &Context, &Placeholder) -> serde_json::Result<Value> + 'static) -> Self {
+    pub fn with_function(mut self, name: impl AsRef<str>, function: impl Fn(&Deserializer, &Context, &Placeholder) -> Result<Value> + Send + Sync + 'static) -> Self {
         self.set_function(name, function);
         self
     }
 
+    /// Set filter.
+    pub fn set_filter(&mut self, name: impl AsRef<str>, filter: impl Fn(&Deserializer, &Context, Value) -> Result<Value> + Send + Sync + 'static) -> &mut Self {
+        Arc::make_mut(&mut self.functions).register_filter(name, filter);
+        self
+    }
+
+    /// Set filter.
+    pub fn with_filter(mut self, name: impl AsRef<str>, filter: impl Fn(&Deserializer, &Context, Value) -> Result<Value> + Send + Sync + 'static) -> Self {
+        self.set_filter(name, filter);
+        self
+    }
+
     /// Get functions.
     pub fn functions(&self) -> &Functions {
         &self.functions
@@ -67,7 +168,7 @@ impl Context {
 
     /// Override data.
     pub fn override_data(&mut self, new_value: Value) -> &mut Self {
-        self.data.override_recursive(new_value);
+        Arc::make_mut(&mut self.data).override_recursive(new_value);
         self
     }
 
@@ -79,36 +180,233 @@ impl Context {
 
     /// Add data.
     pub fn add_data(&mut self, new_value: Value) -> &mut Self {
-        self.data.add_recursive(new_value);
+        Arc::make_mut(&mut self.data).add_recursive(new_value);
         self
     }
 
     /// Set directory.
     pub fn with_directory(mut self, directory: Option<PathBuf>) -> Self {
-        self.directory = directory;
+        self.set_directory(directory);
         self
     }
 
     /// Set directory.
     pub fn set_directory(&mut self, directory: Option<PathBuf>) -> &mut Self {
-        self.directory = directory;
+        self.directory = directory.map(Arc::new);
         self
     }
 
     /// Get directory.
     pub fn directory(&self) -> Option<&PathBuf> {
-        self.directory.as_ref()
+        self.directory.as_deref()
+    }
+
+    /// Set the file system files are read from.
+    pub fn with_file_system(mut self, file_system: impl FileSystem + 'static) -> Self {
+        self.set_file_system(file_system);
+        self
+    }
+
+    /// Set the file system files are read from.
+    pub fn set_file_system(&mut self, file_system: impl FileSystem + 'static) -> &mut Self {
+        self.file_system = Some(Arc::new(file_system));
+        self
+    }
+
+    /// Get the file system files are read from.
+    pub fn file_system(&self) -> &dyn FileSystem {
+        self.file_system.as_deref().unwrap_or(&OsFileSystem)
+    }
+
+    /// Get a shared handle to the file system.
+    pub(crate) fn shared_file_system(&self) -> Arc<dyn FileSystem> {
+        self.file_system.clone().unwrap_or_else(|| Arc::new(OsFileSystem))
+    }
+
+    /// Set the directories searched in order for included files that aren't found in the directory, like `-I` flags.
+    pub fn with_include_directories(mut self, include_directories: Vec<PathBuf>) -> Self {
+        self.set_include_directories(include_directories);
+        self
+    }
+
+    /// Set the directories searched in order for included files that aren't found in the directory, like `-I` flags.
+    pub fn set_include_directories(&mut self, include_directories: Vec<PathBuf>) -> &mut Self {
+        self.include_directories = include_directories.into();
+        self
     }
 
-    pub(crate) fn set_current_data(&mut self, current: Value) {
-        self.current = current;
+    /// Get the directories searched for included files.
+    pub fn include_directories(&self) -> &[PathBuf] {
+        &self.include_directories
+    }
+
+    /// Set the sandbox confining included files to allowed directories.
+    pub fn with_sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.set_sandbox(sandbox);
+        self
+    }
+
+    /// Set the sandbox confining included files to allowed directories.
+    pub fn set_sandbox(&mut self, sandbox: Option<Sandbox>) -> &mut Self {
+        self.sandbox = sandbox.map(Arc::new);
+        self
+    }
+
+    /// Get the sandbox, if set.
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_deref()
+    }
+
+    /// Set whether included files resolve their includes relative to the context directory instead of their own directory.
+    pub fn with_fixed_directory(mut self, fixed_directory: bool) -> Self {
+        self.fixed_directory = fixed_directory;
+        self
+    }
+
+    /// Set whether included files resolve their includes relative to the context directory instead of their own directory.
+    pub fn set_fixed_directory(&mut self, fixed_directory: bool) -> &mut Self {
+        self.fixed_directory = fixed_directory;
+        self
+    }
+
+    /// Get whether included files resolve their includes relative to the context directory.
+    pub fn fixed_directory(&self) -> bool {
+        self.fixed_directory
+    }
+
+    /// Set the cache of included files. Clones of the context share it.
+    pub fn with_file_cache(mut self, file_cache: Option<FileCache>) -> Self {
+        self.set_file_cache(file_cache);
+        self
+    }
+
+    /// Set the cache of included files. Clones of the context share it.
+    pub fn set_file_cache(&mut self, file_cache: Option<FileCache>) -> &mut Self {
+        self.file_cache = file_cache.map(Arc::new);
+        self
+    }
+
+    /// Get the cache of included files, if set.
+    pub fn file_cache(&self) -> Option<&FileCache> {
+        self.file_cache.as_deref()
+    }
+
+    /// Set environment variables, replacing the process environment.
+    pub fn with_environment(mut self, environment: Option<HashMap<String, String>>) -> Self {
+        self.set_environment(environment);
+        self
+    }
+
+    /// Set environment variables, replacing the process environment.
+    pub fn set_environment(&mut self, environment: Option<HashMap<String, String>>) -> &mut Self {
+        self.environment = environment.map(Arc::new);
+        self
+    }
+
+    /// Get environment variables, if set.
+    pub fn environment(&self) -> Option<&HashMap<String, String>> {
+        self.environment.as_deref()
+    }
+
+    /// Get an environment variable from the environment variables, if set, or from the process environment.
+    pub fn environment_variable(&self, name: impl AsRef<str>) -> Option<String> {
+        match &self.environment {
+            Some(environment) => environment.get(name.as_ref()).cloned(),
+            None => std::env::var(name.as_ref()).ok()
+        }
+    }
+
+    /// Get the document being resolved.
+    pub(crate) fn current_data(&self) -> &Value {
+        &self.frame.current
+    }
+
+    pub(crate) fn set_current_data(&mut self, current: Arc<Value>) {
+        self.frame.current = current;
+        self.frame.resolving = Stack::default();
+        self.frame.resolved = Arc::new(Memo { depth: self.frame.scopes.len(), values: Default::default() });
+    }
+
+    /// Resolve the node of the current document at `pointer` at most once per render.
+    /// Nodes referenced from inside loops are resolved without the loop scopes, so every reference gets the same value.
+    pub(crate) fn memoize(&self, pointer: &str, resolve: impl FnOnce(&Self) -> Result<Option<Value>>) -> Result<Option<Value>> {
+        let values = || self.frame.resolved.values.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(value) = values().get(pointer) {
+            return Ok(value.clone())
+        }
+        let depth = self.frame.resolved.depth;
+        let value = if self.frame.scopes.len() == depth {
+            resolve(self)?
+        } else {
+            let mut context = self.clone();
+            context.frame.scopes = self.frame.scopes.truncate(depth);
+            resolve(&context)?
+        };
+        values().insert(pointer.to_string(), value.clone());
+        Ok(value)
+    }
+
+    /// Start including a file, failing if it's already being included.
+    pub(crate) fn enter_file(&mut self, path: PathBuf) -> Result<()> {
+        let path = self.file_system().canonicalize(&path).unwrap_or(path);
+        if self.frame.including.iter().any(|including| *including == path) {
+            let including = self.frame.including.to_vec();
+            let index = including.iter().position(|including| **including == path).unwrap_or_default();
+            let chain = including[index ..]
+                .iter()
+                .copied()
+                .chain(std::iter::once(&path))
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(Error::Cycle { chain });
+        }
+        self.frame.including = self.frame.including.push(path);
+        Ok(())
+    }
+
+    /// Get a context resolving the placeholder `key`, failing if it's already being resolved.
+    pub(crate) fn enter_placeholder(&self, key: &str) -> Result<Self> {
+        if self.frame.resolving.iter().any(|resolving| resolving == key) {
+            let resolving = self.frame.resolving.to_vec();
+            let index = resolving.iter().position(|resolving| *resolving == key).unwrap_or_default();
+            let chain = resolving[index ..]
+                .iter()
+                .map(|resolving| resolving.to_string())
+                .chain(std::iter::once(key.to_string()))
+                .collect();
+            return Err(Error::Cycle { chain });
+        }
+        let mut context = self.clone();
+        context.frame.resolving = self.frame.resolving.push(key.to_string());
+        Ok(context)
+    }
+
+    /// Get a context whose scope variables shadow the data.
+    pub(crate) fn with_scope(&self, scope: Value) -> Self {
+        let mut context = self.clone();
+        context.frame.scopes = self.frame.scopes.push(scope);
+        context
     }
 
     /// Find placeholder value.
-    pub fn find(&self, deserializer: &Deserializer, placeholder: &Placeholder) -> serde_json::Result<Value> {
+    /// Scoped variables are searched first, then the data and then the JSON data being resolved.
+    /// Nodes of the JSON data being resolved are resolved once and reused by every reference.
+    pub fn find(&self, deserializer: &Deserializer, placeholder: &Placeholder) -> Result<Value> {
+        let path = placeholder.path();
+        self.find_segments(deserializer, path.str(), &Segment::compile(&path, Origin::Template)?)
+    }
+
+    /// Find the value of compiled path segments.
+    pub(crate) fn find_segments(&self, deserializer: &Deserializer, path: &str, segments: &[Segment]) -> Result<Value> {
+        for scope in self.frame.scopes.iter() {
+            match scope.get_segments_deserializing(path, segments, deserializer, self, Origin::Resolved) {
+                Err(error) if error.is_not_found() => continue,
+                result => return result
+            }
+        }
         self
             .data
-            .get_dot_deserializing(placeholder.path(), deserializer, self)
-            .or_else(|_| self.current.get_dot_deserializing(placeholder.path(), deserializer, self))
+            .get_segments_deserializing(path, segments, deserializer, self, Origin::Data)
+            .or_else(|_| self.frame.current.get_segments_deserializing(path, segments, deserializer, self, Origin::Template))
     }
-}
\ No newline at end of file
+}
@@ -1,96 +1,319 @@
 //! Deserializer module.
 
+use std::{io::Write, path::PathBuf, sync::Arc};
+
 use serde::de::DeserializeOwned;
 use serde_json::{Map, Value};
 
-use crate::{Context, Placeholder, ToDeserializable, JSON};
+use crate::{call_filter, call_function, Analysis, Context, Error, Expression, Fallback, Graph, GraphBuilder, Loop, Node, Origin, Part, Path, Placeholder, Result, Syntax, Template, ToDeserializable, JSON};
 
 /// A template.
-#[derive(Default, Clone, Copy)]
-pub struct Deserializer;
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Deserializer {
+    /// Accept comments and trailing commas in JSON.
+    lenient: bool,
+    /// Placeholder syntax.
+    syntax: Syntax
+}
 
 impl Deserializer {
+    /// Template string that removes its key from objects and its element from arrays.
+    /// Data and function values equal to it are kept.
+    pub const OMIT: &'static str = "$omit";
+
     /// Create a new deserializer.
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Set lenient mode, accepting `//` and `/* */` comments and trailing commas in JSON inputs and files.
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Set lenient mode, accepting `//` and `/* */` comments and trailing commas in JSON inputs and files.
+    pub fn set_lenient(&mut self, lenient: bool) -> &mut Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Get lenient mode.
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Set the placeholder syntax, like `${path}` instead of `{path}`. Included files use it too.
+    pub fn with_syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Set the placeholder syntax, like `${path}` instead of `{path}`. Included files use it too.
+    pub fn set_syntax(&mut self, syntax: Syntax) -> &mut Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Get the placeholder syntax.
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
     /// Deserialize .
-    pub fn deserialize<T: DeserializeOwned>(&self, value: impl ToDeserializable) -> serde_json::Result<T> {
+    pub fn deserialize<T: DeserializeOwned>(&self, value: impl ToDeserializable) -> Result<T> {
         let context = Context::new();
         self.deserialize_with_context(value, &context)
     }
 
     /// Deserialize with context.
-    pub fn deserialize_with_context<T: DeserializeOwned>(&self, value: impl ToDeserializable, context: &Context) -> serde_json::Result<T> {
+    pub fn deserialize_with_context<T: DeserializeOwned>(&self, value: impl ToDeserializable, context: &Context) -> Result<T> {
+        serde_json::from_value(self.render(value, context)?).map_err(Error::from)
+    }
+
+    /// Render to a JSON value.
+    pub fn render(&self, value: impl ToDeserializable, context: &Context) -> Result<Value> {
+        self.resolve_input(value, context)
+    }
+
+    /// Render and write as JSON text, pretty-printed if `pretty` is set.
+    pub fn render_to_writer(&self, value: impl ToDeserializable, context: &Context, writer: impl Write, pretty: bool) -> Result<()> {
+        let value = self.render(value, context)?;
+        if pretty {
+            serde_json::to_writer_pretty(writer, &value)?;
+        } else {
+            serde_json::to_writer(writer, &value)?;
+        }
+        Ok(())
+    }
+
+    /// Find every placeholder of a template and check whether it can be resolved, without stopping at the first failure.
+    /// Nothing is rendered: paths are looked up without resolving them, functions and filters are looked up by name and included files are located.
+    pub fn analyze(&self, value: impl ToDeserializable, context: &Context) -> Result<Analysis> {
+        let (path, value) = value.to_deserializable(self, context)?;
+        let value = Arc::new(value);
+        let context = self.prepare_context(context, path, value.clone())?;
+        let mut analysis = Analysis::default();
+        analysis.collect(self, &value, "", &context, &[]);
+        Ok(analysis)
+    }
+
+    /// Build the graph of the placeholder references and includes of a template, without rendering it.
+    /// Included files are added once, with their own references.
+    pub fn dependencies(&self, value: impl ToDeserializable, context: &Context) -> Result<Graph> {
+        let (path, value) = value.to_deserializable(self, context)?;
+        let path = path.map(|path| context.file_system().canonicalize(&path).unwrap_or(path));
+        let mut builder = GraphBuilder::new(self);
+        builder.add_document(path, value, context)?;
+        Ok(builder.finish())
+    }
+
+    /// Compile a template to render it many times.
+    pub fn compile(&self, value: impl ToDeserializable) -> Result<Template> {
+        self.compile_with_context(value, &Context::new())
+    }
+
+    /// Compile a template to render it many times, reading files from the context file system.
+    pub fn compile_with_context(&self, value: impl ToDeserializable, context: &Context) -> Result<Template> {
+        let (path, value) = value.to_deserializable(self, context)?;
+        Template::new(*self, path, value)
+    }
+
+    /// Resolve an input in its own context.
+    pub(crate) fn resolve_input(&self, value: impl ToDeserializable, context: &Context) -> Result<Value> {
+        self.compile_with_context(value, context)?.resolve(self, context)
+    }
+
+    /// Create the context to resolve a document, read from `path` if any.
+    /// Files resolve their includes relative to their own directory, unless the context directory is fixed.
+    pub(crate) fn prepare_context(&self, context: &Context, path: Option<PathBuf>, value: Arc<Value>) -> Result<Context> {
         let mut context = context.clone();
-        let (directory, value) = value.to_deserializable()?;
-        if let (Some(directory), None) = (directory, context.directory()) {
-            context.set_directory(Some(directory));
+        if let Some(path) = path {
+            if let Some(directory) = path.parent().filter(|_| !context.fixed_directory() || context.directory().is_none()) {
+                context.set_directory(Some(directory.to_path_buf()));
+            }
+            context.enter_file(path)?;
         }
-        context.set_current_data(value.clone());
-        let value = self.resolve_value(&value, &mut context)?;
-        Ok(serde_json::from_value(serde_json::Value::from(value))?)
+        context.set_current_data(value);
+        Ok(context)
     }
 
     /// Resolve an object.
-    pub fn resolve_object(&self, object: &Map<String, Value>, context: &Context) -> serde_json::Result<Value> {
-        let mut resolving_object = object.clone();
-        for value in resolving_object.values_mut() {
-            *value = self.resolve_value(value, context)?;
-        }
-        Ok(Value::Object(resolving_object))
+    /// Objects with directive keys, like `$if`, are replaced by the directive result.
+    pub fn resolve_object(&self, object: &Map<String, Value>, context: &Context) -> Result<Value> {
+        self.render_node(&Node::object(object, self.syntax, Origin::Template)?, context).map(Option::unwrap_or_default)
     }
 
-    /// Resolve a value.
-    pub fn resolve_value(&self, value: &Value, context: &Context) -> serde_json::Result<Value> {
-        let mut value = value.clone();
-        match &value {
-            Value::Object(object) => value = self.resolve_object(object, context)?,
-            Value::Array(array) => value = self.resolve_array(array, context)?,
-            Value::String(string) => value = self.resolve_string(string, context)?,
-            _ => {}
-        };
-        Ok(value)
+    /// Render the items of a compiled `$for` loop.
+    fn render_loop_items(&self, loop_: &Loop, context: &Context) -> Result<Vec<Value>> {
+        match self.render_node(&loop_.items, context)?.unwrap_or_default() {
+            Value::Array(items) => Ok(items),
+            value => Err(Error::syntax(&loop_.expression, format!("Expected an array, found {}", value)))
+        }
     }
 
-    /// Resolve a string.
-    pub fn resolve_string(&self, string: &str, context: &Context) -> serde_json::Result<Value> {
-        let placeholders = Placeholder::placeholders(string);
-        if placeholders.len() == 1 {
-            if placeholders[0].value == string {
-                return self.resolve_placeholder(&placeholders[0], context)
-            }
+    /// Render a compiled `$for` loop into one value per item.
+    /// `item`, `$index`, `$first` and `$last` can be used in `$do`.
+    fn render_loop(&self, loop_: &Loop, context: &Context) -> Result<Vec<Value>> {
+        let items = self.render_loop_items(loop_, context)?;
+        let mut values = Vec::with_capacity(items.len());
+        for (index, item) in items.iter().enumerate() {
+            let context = context.with_scope(Self::loop_scope(&loop_.variable, item, index, items.len()));
+            values.extend(self.render_node(&loop_.body, &context)?);
         }
-        let string = placeholders.iter().fold(string.to_string(), |acc, placeholder| {
-            acc.replace(&placeholder.value, &self.resolve_placeholder(placeholder, context).unwrap().to_text())
-        });
-        Ok(Value::String(string))
+        Ok(values)
+    }
+
+    /// Scope of the loop iteration `index` out of `count`, binding `variable` to `item`.
+    pub(crate) fn loop_scope(variable: &str, item: &Value, index: usize, count: usize) -> Value {
+        let mut scope = Map::new();
+        scope.insert(variable.to_string(), item.clone());
+        scope.insert("$index".to_string(), index.into());
+        scope.insert("$first".to_string(), (index == 0).into());
+        scope.insert("$last".to_string(), (index + 1 == count).into());
+        Value::Object(scope)
+    }
+
+    /// Whether the object is a directive that needs to be resolved to be traversed.
+    pub(crate) fn is_directive(object: &Map<String, Value>) -> bool {
+        object.contains_key("$if") || object.contains_key("$for")
+    }
+
+    /// Resolve a value. An omitted value resolves to `null`.
+    pub fn resolve_value(&self, value: &Value, context: &Context) -> Result<Value> {
+        self.render_value(value, Origin::Template, None, context).map(Option::unwrap_or_default)
+    }
+
+    /// Resolve a string. An omitted value resolves to `null`.
+    pub fn resolve_string(&self, string: &str, context: &Context) -> Result<Value> {
+        self.render_node(&Node::string(string, self.syntax, Origin::Template)?, context).map(Option::unwrap_or_default)
     }
 
     /// Resolve array.
-    pub fn resolve_array(&self, array: &Vec<Value>, context: &Context) -> serde_json::Result<Value> {
-        let mut resolving_array = array.clone();
-        for value in resolving_array.iter_mut() {
-            *value = self.resolve_value(value, context)?;
-        }
-        Ok(Value::Array(resolving_array))
+    pub fn resolve_array(&self, array: &[Value], context: &Context) -> Result<Value> {
+        self.render_node(&Node::array(array, self.syntax, Origin::Template)?, context).map(Option::unwrap_or_default)
     }
     
     /// Resolve the placeholder.
-    pub fn resolve_placeholder(&self, placeholder: &Placeholder, context: &Context) -> serde_json::Result<Value> {
+    pub fn resolve_placeholder(&self, placeholder: &Placeholder, context: &Context) -> Result<Value> {
+        self.render_expression(&Expression::new(placeholder.clone(), Origin::Template)?, context).map(Option::unwrap_or_default)
+    }
+
+    /// Render a value from `origin`, memoizing its nodes if it's the node of the current document at `pointer`.
+    pub(crate) fn render_value(&self, value: &Value, origin: Origin, pointer: Option<&str>, context: &Context) -> Result<Option<Value>> {
+        self.render_node_at(&Node::compile(value, self.syntax, origin)?, pointer, context)
+    }
+
+    /// Render a compiled document, resolving each of its nodes at most once. An omitted document renders as `null`.
+    pub(crate) fn render_document(&self, node: &Node, context: &Context) -> Result<Value> {
+        self.render_node_at(node, Some(""), context).map(Option::unwrap_or_default)
+    }
+
+    /// Render a compiled value, `None` if it's omitted.
+    pub(crate) fn render_node(&self, node: &Node, context: &Context) -> Result<Option<Value>> {
+        self.render_node_at(node, None, context)
+    }
+
+    /// Render a compiled value, memoizing it if it's a node of the current document at `pointer`.
+    fn render_node_at(&self, node: &Node, pointer: Option<&str>, context: &Context) -> Result<Option<Value>> {
+        if let (Some(pointer), Node::Placeholder(_) | Node::Text { .. } | Node::Conditional { .. } | Node::Loop(_)) = (pointer, node) {
+            return context
+                .memoize(pointer, |context| self.render_node(node, context))
+                .map_err(|error| error.in_cycle_from(&Path::from_pointer(pointer, self.syntax.path_separator())))
+        }
+        match node {
+            Node::Literal(value) => Ok(Some(value.clone())),
+            Node::Omit => Ok(None),
+            Node::Placeholder(expression) => self
+                .render_expression(expression, context)
+                .map_err(|error| error.in_template(&expression.placeholder.value)),
+            Node::Text { source, parts } => {
+                let mut resolved = String::with_capacity(source.len());
+                for part in parts {
+                    match part {
+                        Part::Text(text) => resolved.push_str(text),
+                        Part::Placeholder(expression) => {
+                            let value = self
+                                .render_expression(expression, context)
+                                .map_err(|error| error.in_template(source))?;
+                            // An omitted placeholder omits the whole string.
+                            let Some(value) = value else { return Ok(None) };
+                            resolved.push_str(&value.to_text());
+                        }
+                    }
+                }
+                Ok(Some(Value::String(resolved)))
+            },
+            Node::Array(nodes) => {
+                let mut array = Vec::with_capacity(nodes.len());
+                for (index, node) in nodes.iter().enumerate() {
+                    // Loops are spliced into the array they're in.
+                    if let Node::Loop(loop_) = node {
+                        array.extend(self.render_loop(loop_, context)?);
+                        continue;
+                    }
+                    let pointer = pointer.map(|pointer| Path::pointer(pointer, &index.to_string()));
+                    array.extend(self.render_node_at(node, pointer.as_deref(), context)?);
+                }
+                Ok(Some(Value::Array(array)))
+            },
+            Node::Object(entries) => {
+                let mut object = Map::new();
+                for (key, node) in entries {
+                    let pointer = pointer.map(|pointer| Path::pointer(pointer, key));
+                    if let Some(value) = self.render_node_at(node, pointer.as_deref(), context)? {
+                        object.insert(key.clone(), value);
+                    }
+                }
+                Ok(Some(Value::Object(object)))
+            },
+            Node::Conditional { condition, then, otherwise } => {
+                let condition = self.render_node(condition, context)?.unwrap_or_default();
+                // A missing branch omits the value.
+                match if condition.is_truthy() { then } else { otherwise } {
+                    Some(node) => self.render_node(node, context),
+                    None => Ok(None)
+                }
+            },
+            Node::Loop(loop_) => self.render_loop(loop_, context).map(|values| Some(Value::Array(values)))
+        }
+    }
+
+    /// Render a compiled placeholder, `None` if it's omitted.
+    pub(crate) fn render_expression(&self, expression: &Expression, context: &Context) -> Result<Option<Value>> {
+        self.render_expression_value(expression, &context.enter_placeholder(&expression.key)?)
+    }
+
+    fn render_expression_value(&self, expression: &Expression, context: &Context) -> Result<Option<Value>> {
+        let placeholder = &expression.placeholder;
         let value = if let Some(type_) = placeholder.type_.as_ref() {
-            context
-                .functions()
-                .get(type_)
-                .ok_or_else(|| serde::de::Error::custom(format!("Function not found: {:?}", placeholder)))
-                .and_then(|function| function(self, context, placeholder))
+            call_function(self, context, type_, placeholder)
+                .map_err(|error| error.in_function(type_))
+                .and_then(|value| match context.functions().builtin(type_) {
+                    // Built-in functions return resolved values, like included files or environment variables.
+                    Some(_) => Ok(Some(value)),
+                    // Placeholders in custom function values are resolved recursively, without unescaping them.
+                    None => self.render_value(&value, Origin::Data, None, context)
+                })
         } else {
             context
-                .find(self, &placeholder)
+                .find_segments(self, &expression.path, &expression.segments)
+                .map(Some)
+        };
+        let value = match (value, &expression.fallback) {
+            (Err(error), Some(fallback)) if error.is_not_found() => match fallback {
+                Fallback::Literal(value) => Ok(Some(value.clone())),
+                Fallback::Template(node) => self.render_node(node, context)
+            },
+            (value, _) => value
         }?;
-        // Resolve placeholders recursively
-        let value = self.resolve_value(&value, context)?;
-        Ok(value)
+        value
+            .map(|value| expression.filters.iter().try_fold(value, |value, name| self.apply_filter(name, value, context)))
+            .transpose()
     }
-}
+
+    /// Apply a filter to a resolved value.
+    fn apply_filter(&self, name: &str, value: Value, context: &Context) -> Result<Value> {
+        call_filter(self, context, name, value).map_err(|error| error.in_function(name))
+    }
+}
\ No newline at end of file
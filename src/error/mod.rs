@@ -0,0 +1,189 @@
+//! Error module.
+
+use std::{fmt::Display, path::PathBuf};
+
+/// Template resolution error.
+#[derive(Debug)]
+pub enum Error {
+    /// A placeholder path couldn't be found in the data nor in the document being resolved.
+    PathNotFound {
+        /// The path that couldn't be found.
+        path: String,
+        /// The placeholder that referenced the path, if known.
+        template_location: Option<String>
+    },
+    /// A placeholder references a function that isn't registered.
+    FunctionNotFound {
+        /// The function name.
+        name: String
+    },
+    /// An included file couldn't be found in any of the include directories.
+    FileNotFound {
+        /// The included path.
+        path: String,
+        /// Every location that was tried, in order.
+        locations: Vec<PathBuf>
+    },
+    /// An included file is outside of the context sandbox.
+    Sandbox {
+        /// The refused path.
+        path: PathBuf,
+        /// Why it was refused.
+        message: String
+    },
+    /// A file couldn't be read.
+    Io {
+        /// The file path.
+        path: PathBuf,
+        /// The underlying error.
+        source: std::io::Error
+    },
+    /// A template string is malformed.
+    Syntax {
+        /// The offending template string.
+        template: String,
+        /// What is wrong with it.
+        message: String
+    },
+    /// A JSON document couldn't be parsed or deserialized.
+    Parse {
+        /// The file being parsed, if any.
+        path: Option<PathBuf>,
+        /// Line and column of the error, starting at 1, if known.
+        location: Option<(usize, usize)>,
+        /// The underlying error.
+        source: serde_json::Error
+    },
+    /// Placeholders reference each other in a cycle.
+    Cycle {
+        /// The chain of references, starting and ending with the same entry.
+        chain: Vec<String>
+    },
+    /// A function returned an error.
+    FunctionFailed {
+        /// The function name.
+        name: String,
+        /// The error returned by the function.
+        source: Box<Error>
+    },
+    /// Custom error message.
+    Custom(String)
+}
+
+/// Template resolution result.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Create a custom error.
+    pub fn custom(message: impl Display) -> Self {
+        Self::Custom(message.to_string())
+    }
+
+    /// Create a syntax error for the template string.
+    pub fn syntax(template: impl AsRef<str>, message: impl Display) -> Self {
+        Self::Syntax { template: template.as_ref().to_string(), message: message.to_string() }
+    }
+
+    /// Whether the error means a value doesn't exist, like a missing path or file.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Self::PathNotFound { .. } | Self::FileNotFound { .. } => true,
+            Self::Io { source, .. } => source.kind() == std::io::ErrorKind::NotFound,
+            Self::FunctionFailed { source, .. } => source.is_not_found(),
+            _ => false
+        }
+    }
+
+    /// Set the template string where a missing path was referenced, if not set yet.
+    pub(crate) fn in_template(self, template: &str) -> Self {
+        match self {
+            Self::PathNotFound { path, template_location: None } => Self::PathNotFound { path, template_location: Some(template.to_string()) },
+            error => error
+        }
+    }
+
+    /// Start a cycle chain at `key`, the value whose resolution found the cycle, if it's part of it.
+    pub(crate) fn in_cycle_from(self, key: &str) -> Self {
+        match self {
+            Self::Cycle { mut chain } => {
+                let cycle = chain.len().saturating_sub(1);
+                if let Some(index) = chain[.. cycle].iter().position(|entry| entry == key) {
+                    chain.truncate(cycle);
+                    chain.rotate_left(index);
+                    chain.push(key.to_string());
+                }
+                Self::Cycle { chain }
+            },
+            error => error
+        }
+    }
+
+    /// Create a parse error from another format, at `location` if known.
+    pub(crate) fn parse(error: impl Display, location: Option<(usize, usize)>) -> Self {
+        Self::Parse { path: None, location, source: serde::de::Error::custom(error) }
+    }
+
+    /// Attribute an error returned by the function `name` to it.
+    /// Errors that already carry their own context are kept as they are.
+    pub(crate) fn in_function(self, name: impl AsRef<str>) -> Self {
+        match self {
+            Self::Custom(_) | Self::Parse { .. } => Self::FunctionFailed { name: name.as_ref().to_string(), source: Box::new(self) },
+            error => error
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PathNotFound { path, template_location: Some(location) } => write!(f, "Path not found: {} in {}", path, location),
+            Self::PathNotFound { path, template_location: None } => write!(f, "Path not found: {}", path),
+            Self::FunctionNotFound { name } => write!(f, "Function not found: {}", name),
+            Self::FileNotFound { path, locations } => {
+                let locations = locations.iter().map(|location| location.display().to_string()).collect::<Vec<_>>();
+                write!(f, "File not found: {}, tried {}", path, locations.join(", "))
+            },
+            Self::Sandbox { path, message } => write!(f, "Access to {} denied: {}", path.display(), message),
+            Self::Syntax { template, message } => write!(f, "{} in {:?}", message, template),
+            Self::Io { path, source } => write!(f, "{} - {}", source, path.display()),
+            Self::Parse { path: Some(path), source, .. } => write!(f, "{} - {}", source, path.display()),
+            Self::Parse { path: None, source, .. } => write!(f, "{}", source),
+            Self::Cycle { chain } => write!(f, "Cycle detected: {}", chain.join(" -> ")),
+            Self::FunctionFailed { name, source } => write!(f, "Function {} failed: {}", name, source),
+            Self::Custom(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Parse { source, .. } => Some(source),
+            Self::FunctionFailed { source, .. } => Some(source.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: Display>(message: T) -> Self {
+        Self::custom(message)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(source: serde_json::Error) -> Self {
+        let location = (source.line() > 0).then(|| (source.line(), source.column()));
+        Self::Parse { path: None, location, source }
+    }
+}
+
+impl From<Error> for serde_json::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Parse { path: None, source, .. } => source,
+            error => serde::de::Error::custom(error)
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! File system module.
+
+use std::{collections::HashMap, io::{Error, ErrorKind, Result}, path::{Component, Path, PathBuf}};
+
+/// Files templates are read from.
+pub trait FileSystem: Send + Sync {
+    /// Read a file.
+    fn read_to_string(&self, path: &Path) -> Result<String>;
+
+    /// Whether a file exists.
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Get the canonical path of a file or directory, failing if it doesn't exist.
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf>;
+
+    /// Whether the path is a symbolic link.
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+/// The operating system file system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        std::fs::canonicalize(path)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink())
+    }
+}
+
+/// Files kept in memory, useful for tests.
+/// Paths are compared once made absolute, relative to the current directory, and with their `.` and `..` components resolved.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFileSystem {
+    files: HashMap<PathBuf, String>
+}
+
+impl MemoryFileSystem {
+    /// Create an empty file system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file.
+    pub fn with_file(mut self, path: impl AsRef<Path>, contents: impl Into<String>) -> Self {
+        self.set_file(path, contents);
+        self
+    }
+
+    /// Add a file.
+    pub fn set_file(&mut self, path: impl AsRef<Path>, contents: impl Into<String>) -> &mut Self {
+        self.files.insert(absolute(path.as_ref()), contents.into());
+        self
+    }
+
+    /// Remove a file, returning its contents.
+    pub fn remove_file(&mut self, path: impl AsRef<Path>) -> Option<String> {
+        self.files.remove(&absolute(path.as_ref()))
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        self.files.get(&absolute(path)).cloned().ok_or_else(|| Error::from(ErrorKind::NotFound))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&absolute(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        let path = absolute(path);
+        if self.files.keys().any(|file| file.starts_with(&path)) {
+            Ok(path)
+        } else {
+            Err(Error::from(ErrorKind::NotFound))
+        }
+    }
+}
+
+/// Files compiled into the binary, for instance with `include_str!`.
+/// Paths are compared once made absolute, relative to the current directory, and with their `.` and `..` components resolved.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EmbeddedFileSystem {
+    files: &'static [(&'static str, &'static str)]
+}
+
+impl EmbeddedFileSystem {
+    /// Create a file system from `(path, contents)` pairs.
+    pub const fn new(files: &'static [(&'static str, &'static str)]) -> Self {
+        Self { files }
+    }
+
+    fn get(&self, path: &Path) -> Option<&'static str> {
+        let path = absolute(path);
+        self.files
+            .iter()
+            .find(|(file, _)| absolute(Path::new(file)) == path)
+            .map(|(_, contents)| *contents)
+    }
+}
+
+impl FileSystem for EmbeddedFileSystem {
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        self.get(path).map(String::from).ok_or_else(|| Error::from(ErrorKind::NotFound))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.get(path).is_some()
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        let path = absolute(path);
+        if self.files.iter().any(|(file, _)| absolute(Path::new(file)).starts_with(&path)) {
+            Ok(path)
+        } else {
+            Err(Error::from(ErrorKind::NotFound))
+        }
+    }
+}
+
+/// Resolve the `.` and `..` components of a path, without following symbolic links.
+/// Leading `..` components of relative paths are kept.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            },
+            Component::ParentDir if normalized.has_root() => {},
+            component => normalized.push(component)
+        }
+    }
+    normalized
+}
+
+/// Make a path absolute, relative to the current directory, and resolve its `.` and `..` components, without following symbolic links.
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    normalize(&std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+}
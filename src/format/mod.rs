@@ -0,0 +1,150 @@
+//! File formats module.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::{Error, FileSystem, Result};
+
+/// File format of a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// JSON.
+    Json,
+    /// JSON with comments and trailing commas.
+    Jsonc,
+    /// YAML, requires the `yaml` feature.
+    Yaml,
+    /// TOML, requires the `toml` feature.
+    Toml,
+    /// JSON5, requires the `json5` feature.
+    Json5
+}
+
+impl Format {
+    /// Get the format from the file extension, defaulting to JSON.
+    pub(crate) fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("jsonc") => Self::Jsonc,
+            Some("toml") => Self::Toml,
+            Some("json5") => Self::Json5,
+            _ => Self::Json
+        }
+    }
+
+    /// Use the lenient JSON format instead of JSON if `lenient` is set.
+    pub(crate) fn lenient(self, lenient: bool) -> Self {
+        match self {
+            Self::Json if lenient => Self::Jsonc,
+            format => format
+        }
+    }
+
+    /// Parse a text in this format, keeping the location of errors.
+    pub(crate) fn parse(&self, text: &str) -> Result<Value> {
+        match self {
+            Self::Json => serde_json::from_str(text).map_err(Error::from),
+            Self::Jsonc => serde_json::from_str(&strip_jsonc(text)).map_err(Error::from),
+            #[cfg(feature = "yaml")]
+            Self::Yaml => serde_yaml::from_str(text).map_err(|error| {
+                let location = error.location().map(|location| (location.line(), location.column()));
+                Error::parse(error, location)
+            }),
+            #[cfg(feature = "toml")]
+            Self::Toml => toml::from_str(text).map_err(|error| {
+                let location = error.span().map(|span| line_column(text, span.start));
+                Error::parse(error, location)
+            }),
+            #[cfg(feature = "json5")]
+            Self::Json5 => json5::from_str(text).map_err(|error| {
+                let json5::Error::Message { location, .. } = &error;
+                let location = location.as_ref().map(|location| (location.line, location.column));
+                Error::parse(error, location)
+            }),
+            #[allow(unreachable_patterns)]
+            format => Err(Error::parse(format!("{:?} support requires the `{}` feature", format, format.feature()), None))
+        }
+    }
+
+    /// Cargo feature enabling the format.
+    fn feature(&self) -> &'static str {
+        match self {
+            Self::Json | Self::Jsonc => "",
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+            Self::Json5 => "json5"
+        }
+    }
+
+    /// Read and parse a file in this format.
+    pub(crate) fn read(&self, path: &Path, file_system: &dyn FileSystem) -> Result<Value> {
+        let text = file_system.read_to_string(path)
+            .map_err(|source| Error::Io { path: path.to_path_buf(), source })?;
+        self.parse(&text).map_err(|error| match error {
+            Error::Parse { location, source, .. } => Error::Parse { path: Some(path.to_path_buf()), location, source },
+            error => error
+        })
+    }
+}
+
+/// Line and column, starting at 1, of a byte index in a text.
+#[cfg(feature = "toml")]
+fn line_column(text: &str, index: usize) -> (usize, usize) {
+    let before = &text[.. index.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (before.matches('\n').count() + 1, before[line_start ..].chars().count() + 1)
+}
+
+/// Replace comments and trailing commas with whitespaces, so positions in errors still match the original text.
+fn strip_jsonc(text: &str) -> String {
+    let mut bytes = text.as_bytes().to_vec();
+    // Comments.
+    let mut index = 0;
+    let mut in_string = false;
+    while index < bytes.len() {
+        match (in_string, bytes[index]) {
+            (true, b'\\') => index += 1,
+            (true, b'"') => in_string = false,
+            (false, b'"') => in_string = true,
+            (false, b'/') if bytes.get(index + 1) == Some(&b'/') => {
+                while index < bytes.len() && bytes[index] != b'\n' {
+                    bytes[index] = b' ';
+                    index += 1;
+                }
+                continue;
+            }
+            (false, b'/') if bytes.get(index + 1) == Some(&b'*') => {
+                let end = text[index + 2 ..].find("*/").map(|end| index + 2 + end + 2).unwrap_or(bytes.len());
+                for byte in &mut bytes[index .. end] {
+                    if !byte.is_ascii_whitespace() {
+                        *byte = b' ';
+                    }
+                }
+                index = end;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    // Trailing commas.
+    let mut index = 0;
+    let mut in_string = false;
+    while index < bytes.len() {
+        match (in_string, bytes[index]) {
+            (true, b'\\') => index += 1,
+            (true, b'"') => in_string = false,
+            (false, b'"') => in_string = true,
+            (false, b',') => {
+                let next = bytes[index + 1 ..].iter().find(|byte| !byte.is_ascii_whitespace());
+                if matches!(next, Some(b'}' | b']')) {
+                    bytes[index] = b' ';
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
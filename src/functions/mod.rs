@@ -1,35 +1,150 @@
 //! Functions module.
 
-use std::{collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, path::PathBuf, rc::Rc, sync::Arc};
 
 use serde_json::Value;
 
-use crate::{Context, Deserializer, Placeholder, JSON};
+use crate::{Context, Deserializer, Error, Format, Placeholder, Result, JSON};
 
+/// A function callable from a placeholder, like `{name:path}`.
+pub type Function = dyn Fn(&Deserializer, &Context, &Placeholder) -> Result<Value> + Send + Sync;
+
+/// A filter applied to a placeholder value, like `{path | name}`.
+pub type Filter = dyn Fn(&Deserializer, &Context, Value) -> Result<Value> + Send + Sync;
+
+/// A function that can't be shared between threads, like a closure capturing `Rc` state.
+pub type LocalFunction = dyn Fn(&Deserializer, &Context, &Placeholder) -> Result<Value>;
+
+/// A filter that can't be shared between threads.
+pub type LocalFilter = dyn Fn(&Deserializer, &Context, Value) -> Result<Value>;
 
 /// Functions registry.
 #[derive(Clone)]
 pub struct Functions {
-    registry: HashMap<String, Rc<dyn Fn(&Deserializer, &Context, &Placeholder) -> serde_json::Result<Value>>>
+    registry: HashMap<String, Arc<Function>>,
+    filters: HashMap<String, Arc<Filter>>,
+    /// Built-in functions still registered, by name.
+    builtins: HashMap<String, Builtin>,
+    /// Built-in filters still registered.
+    builtin_filters: HashSet<String>
+}
+
+/// A built-in function, whose dependencies are known without calling it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Builtin {
+    /// `string`, depending on its path.
+    String,
+    /// `file`, `yaml`, `toml` and `json5`, depending on the included file, read in the format if given.
+    Include(Option<Format>),
+    /// `compose`, depending on its placeholders.
+    Compose,
+    /// `env`, depending on an environment variable.
+    Env
 }
 
 /// Transforms everything into a string.
-fn string(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> serde_json::Result<Value> {
+fn string(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> Result<Value> {
     context.find(deserializer, placeholder).map(|value| Value::String(value.to_string()))
 }
 
-/// Reads a file.
-fn file(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> serde_json::Result<Value> {
-    context
-        .directory()
-        .as_ref()
-        .map(|directory| directory.join(placeholder.path().str()))
-        .ok_or_else(|| serde::de::Error::custom("No directory set."))
-        .and_then(|path| deserializer.deserialize_with_context::<Value>(path, context))
+/// Reads a file, in the format given by its extension.
+fn file(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> Result<Value> {
+    include(deserializer, context, placeholder, None)
+}
+
+/// Reads a YAML file.
+#[cfg(feature = "yaml")]
+fn yaml(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> Result<Value> {
+    include(deserializer, context, placeholder, Some(Format::Yaml))
+}
+
+/// Reads a TOML file.
+#[cfg(feature = "toml")]
+fn toml(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> Result<Value> {
+    include(deserializer, context, placeholder, Some(Format::Toml))
+}
+
+/// Reads a JSON5 file.
+#[cfg(feature = "json5")]
+fn json5(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> Result<Value> {
+    include(deserializer, context, placeholder, Some(Format::Json5))
+}
+
+/// Reads a file, through the context file cache if set.
+fn include(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder, format: Option<Format>) -> Result<Value> {
+    let path = locate(context, placeholder.path().str())?;
+    let format = format.unwrap_or_else(|| Format::from_path(&path)).lenient(deserializer.lenient());
+    match context.file_cache() {
+        Some(cache) => {
+            let path = context.file_system().canonicalize(&path).map_err(|source| Error::Io { path, source })?;
+            let syntax = deserializer.syntax();
+            cache.render(&path, format, syntax, || {
+                cache
+                    .template(&path, context.shared_file_system(), format, syntax, || deserializer.compile_with_context((path.as_path(), format), context))?
+                    .resolve(deserializer, context)
+            })
+        },
+        None => deserializer.resolve_input((path.as_path(), format), context)
+    }
+}
+
+/// Find an included file in the context directory or, if it isn't there, in the first include directory that has it.
+/// Files outside of the context sandbox, if set, are refused, and the path checked by the sandbox is returned.
+pub(crate) fn locate(context: &Context, path: &str) -> Result<PathBuf> {
+    let mut locations = Vec::new();
+    for directory in context.directory().into_iter().chain(context.include_directories()) {
+        let location = directory.join(path);
+        if !locations.contains(&location) {
+            locations.push(location);
+        }
+    }
+    if locations.is_empty() {
+        return Err(Error::custom("No directory set."))
+    }
+    let mut denied = None;
+    for location in &locations {
+        let allowed = match context.sandbox() {
+            Some(sandbox) => sandbox.check(location, context.file_system()),
+            None => Ok(location.clone())
+        };
+        match allowed {
+            Ok(location) if context.file_system().is_file(&location) => return Ok(location),
+            Ok(_) | Err(Error::Io { .. }) => {},
+            Err(error) => {
+                denied.get_or_insert(error);
+            }
+        }
+    }
+    Err(denied.unwrap_or_else(|| Error::FileNotFound { path: path.to_string(), locations }))
+}
+
+/// Reads an environment variable, optionally parsing it as `int`, `float`, `bool` or `json`.
+/// Examples: `{env:HOME}`, `{env:int:PORT}`.
+/// A fallback is parsed like the variable, so `{env:PORT ?? 8080}` is a string whether `PORT` is set or not.
+fn env(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> Result<Value> {
+    let path = placeholder.path();
+    let (type_, name) = match path.str().split_once(placeholder.syntax().function_separator()) {
+        Some((type_, name)) if matches!(type_, "int" | "float" | "bool" | "json" | "string") => (type_, name),
+        _ => ("string", path.str())
+    };
+    let variable = match (context.environment_variable(name), placeholder.fallback()) {
+        (Some(variable), _) => variable,
+        (None, Some(fallback)) if fallback != Deserializer::OMIT => deserializer.resolve_string(fallback, context)?.to_text(),
+        (None, _) => return Err(Error::PathNotFound { path: name.to_string(), template_location: None })
+    };
+    let invalid = |error: &dyn std::fmt::Display| Error::custom(format!("{} is not a valid {}: {}", name, type_, error));
+    let value = match type_ {
+        "int" => variable.trim().parse::<i64>().map(Value::from).map_err(|error| invalid(&error))?,
+        "float" => variable.trim().parse::<f64>().map(Value::from).map_err(|error| invalid(&error))?,
+        "bool" => variable.trim().parse::<bool>().map(Value::from).map_err(|error| invalid(&error))?,
+        "json" => serde_json::from_str(&variable).map_err(|error| invalid(&error))?,
+        _ => Value::String(variable)
+    };
+    Ok(value)
 }
 
 /// Composes a value from multiple placeholders.
-fn compose(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> serde_json::Result<Value> {
+fn compose(deserializer: &Deserializer, context: &Context, placeholder: &Placeholder) -> Result<Value> {
     let path = placeholder.path();
     let parts = path.str().split(',').collect::<Vec<_>>();
     let mut value = Value::Object(Default::default());
@@ -40,25 +155,200 @@ fn compose(deserializer: &Deserializer, context: &Context, placeholder: &Placeho
     Ok(value)
 }
 
+/// Maps a string value, failing for anything else.
+fn map_string(name: &str, value: Value, map: impl Fn(&str) -> String) -> Result<Value> {
+    value
+        .as_str()
+        .map(|string| Value::String(map(string)))
+        .ok_or_else(|| Error::custom(format!("{} expects a string, found {}", name, value)))
+}
+
+/// Converts a string to upper case.
+fn upper(_deserializer: &Deserializer, _context: &Context, value: Value) -> Result<Value> {
+    map_string("upper", value, str::to_uppercase)
+}
+
+/// Converts a string to lower case.
+fn lower(_deserializer: &Deserializer, _context: &Context, value: Value) -> Result<Value> {
+    map_string("lower", value, str::to_lowercase)
+}
+
+/// Removes leading and trailing whitespaces of a string.
+fn trim(_deserializer: &Deserializer, _context: &Context, value: Value) -> Result<Value> {
+    map_string("trim", value, |string| string.trim().to_string())
+}
+
+/// Transforms everything into a string.
+fn to_string(_deserializer: &Deserializer, _context: &Context, value: Value) -> Result<Value> {
+    Ok(Value::String(value.to_text()))
+}
+
 impl Default for Functions {
     fn default() -> Self {
-        let registry = Default::default();
-        let mut functions = Functions { registry };
+        let mut functions = Functions { registry: Default::default(), filters: Default::default(), builtins: Default::default(), builtin_filters: Default::default() };
         functions.register("string", string);
         functions.register("file", file);
         functions.register("compose", compose);
+        functions.register("env", env);
+        functions.builtins.extend([("string", Builtin::String), ("file", Builtin::Include(None)), ("compose", Builtin::Compose), ("env", Builtin::Env)].map(|(name, builtin)| (name.to_string(), builtin)));
+        #[cfg(feature = "yaml")]
+        {
+            functions.register("yaml", yaml);
+            functions.builtins.insert("yaml".to_string(), Builtin::Include(Some(Format::Yaml)));
+        }
+        #[cfg(feature = "toml")]
+        {
+            functions.register("toml", toml);
+            functions.builtins.insert("toml".to_string(), Builtin::Include(Some(Format::Toml)));
+        }
+        #[cfg(feature = "json5")]
+        {
+            functions.register("json5", json5);
+            functions.builtins.insert("json5".to_string(), Builtin::Include(Some(Format::Json5)));
+        }
+        functions.register_filter("upper", upper);
+        functions.register_filter("lower", lower);
+        functions.register_filter("trim", trim);
+        functions.register_filter("string", to_string);
+        functions.builtin_filters.extend(["upper", "lower", "trim", "string"].map(String::from));
         functions
     }    
 }
 
 impl Functions {
     /// Create a new functions.
-    pub fn register(&mut self, name: impl AsRef<str>, function: impl Fn(&Deserializer, &Context, &Placeholder) -> serde_json::Result<Value> + 'static) {
+    pub fn register(&mut self, name: impl AsRef<str>, function: impl Fn(&Deserializer, &Context, &Placeholder) -> Result<Value> + Send + Sync + 'static) {
+        self.builtins.remove(name.as_ref());
+        self.registry.insert(name.as_ref().to_string(), Arc::new(function));
+    }
+
+    /// Get a function.
+    pub fn get(&self, name: impl AsRef<str>) -> Option<Arc<Function>> {
+        self.registry.get(name.as_ref()).cloned()
+    }
+
+    /// Register a filter.
+    pub fn register_filter(&mut self, name: impl AsRef<str>, filter: impl Fn(&Deserializer, &Context, Value) -> Result<Value> + Send + Sync + 'static) {
+        self.builtin_filters.remove(name.as_ref());
+        self.filters.insert(name.as_ref().to_string(), Arc::new(filter));
+    }
+
+    /// Get a filter.
+    pub fn get_filter(&self, name: impl AsRef<str>) -> Option<Arc<Filter>> {
+        self.filters.get(name.as_ref()).cloned()
+    }
+
+    /// Get the built-in function registered as `name`, if it wasn't replaced.
+    pub(crate) fn builtin(&self, name: &str) -> Option<Builtin> {
+        self.builtins.get(name).copied()
+    }
+
+    /// Whether the filter registered as `name` is a built-in one.
+    pub(crate) fn is_builtin_filter(&self, name: &str) -> bool {
+        self.builtin_filters.contains(name)
+    }
+}
+
+/// Functions and filters that can't be shared between threads, callable from the renders run in `LocalFunctions::scope`.
+/// They're only looked up if the context doesn't register a function or filter with the same name.
+#[derive(Default, Clone)]
+pub struct LocalFunctions {
+    registry: HashMap<String, Rc<LocalFunction>>,
+    filters: HashMap<String, Rc<LocalFilter>>
+}
+
+thread_local! {
+    /// Local functions in scope on the current thread, innermost last.
+    static LOCAL_FUNCTIONS: RefCell<Vec<Rc<LocalFunctions>>> = const { RefCell::new(Vec::new()) };
+}
+
+impl LocalFunctions {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a function.
+    pub fn register(&mut self, name: impl AsRef<str>, function: impl Fn(&Deserializer, &Context, &Placeholder) -> Result<Value> + 'static) {
         self.registry.insert(name.as_ref().to_string(), Rc::new(function));
     }
 
+    /// Register a function.
+    pub fn with_function(mut self, name: impl AsRef<str>, function: impl Fn(&Deserializer, &Context, &Placeholder) -> Result<Value> + 'static) -> Self {
+        self.register(name, function);
+        self
+    }
+
     /// Get a function.
-    pub fn get(&self, name: impl AsRef<str>) -> Option<Rc<dyn Fn(&Deserializer, &Context, &Placeholder) -> serde_json::Result<Value>>> {
+    pub fn get(&self, name: impl AsRef<str>) -> Option<Rc<LocalFunction>> {
         self.registry.get(name.as_ref()).cloned()
     }
-}
\ No newline at end of file
+
+    /// Register a filter.
+    pub fn register_filter(&mut self, name: impl AsRef<str>, filter: impl Fn(&Deserializer, &Context, Value) -> Result<Value> + 'static) {
+        self.filters.insert(name.as_ref().to_string(), Rc::new(filter));
+    }
+
+    /// Register a filter.
+    pub fn with_filter(mut self, name: impl AsRef<str>, filter: impl Fn(&Deserializer, &Context, Value) -> Result<Value> + 'static) -> Self {
+        self.register_filter(name, filter);
+        self
+    }
+
+    /// Get a filter.
+    pub fn get_filter(&self, name: impl AsRef<str>) -> Option<Rc<LocalFilter>> {
+        self.filters.get(name.as_ref()).cloned()
+    }
+
+    /// Run `scope` with the functions and filters callable from its renders on the current thread.
+    /// Renders on other threads don't see them.
+    pub fn scope<T>(&self, scope: impl FnOnce() -> T) -> T {
+        /// Takes the functions out of scope, even if `scope` panics.
+        struct Guard;
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                LOCAL_FUNCTIONS.with(|local| local.borrow_mut().pop());
+            }
+        }
+        LOCAL_FUNCTIONS.with(|local| local.borrow_mut().push(Rc::new(self.clone())));
+        let _guard = Guard;
+        scope()
+    }
+
+    /// Find a function or filter in the local functions in scope, innermost first.
+    fn find<T>(find: impl Fn(&LocalFunctions) -> Option<T>) -> Option<T> {
+        LOCAL_FUNCTIONS.with(|local| local.borrow().iter().rev().find_map(|functions| find(functions)))
+    }
+}
+
+/// Call the function `name`, registered in the context or local to the current thread.
+pub(crate) fn call_function(deserializer: &Deserializer, context: &Context, name: &str, placeholder: &Placeholder) -> Result<Value> {
+    if let Some(function) = context.functions().get(name) {
+        return function(deserializer, context, placeholder)
+    }
+    match LocalFunctions::find(|functions| functions.get(name)) {
+        Some(function) => function(deserializer, context, placeholder),
+        None => Err(Error::FunctionNotFound { name: name.to_string() })
+    }
+}
+
+/// Call the filter `name`, registered in the context or local to the current thread.
+pub(crate) fn call_filter(deserializer: &Deserializer, context: &Context, name: &str, value: Value) -> Result<Value> {
+    if let Some(filter) = context.functions().get_filter(name) {
+        return filter(deserializer, context, value)
+    }
+    match LocalFunctions::find(|functions| functions.get_filter(name)) {
+        Some(filter) => filter(deserializer, context, value),
+        None => Err(Error::FunctionNotFound { name: name.to_string() })
+    }
+}
+
+/// Whether the function `name` is registered in the context or local to the current thread.
+pub(crate) fn has_function(context: &Context, name: &str) -> bool {
+    context.functions().get(name).is_some() || LocalFunctions::find(|functions| functions.get(name)).is_some()
+}
+
+/// Whether the filter `name` is registered in the context or local to the current thread.
+pub(crate) fn has_filter(context: &Context, name: &str) -> bool {
+    context.functions().get_filter(name).is_some() || LocalFunctions::find(|functions| functions.get_filter(name)).is_some()
+}
@@ -0,0 +1,390 @@
+//! Dependency graph module.
+
+use std::{borrow::Cow, collections::{BTreeMap, BTreeSet}, fmt::Display, path::PathBuf, sync::Arc};
+
+use serde_json::Value;
+
+use crate::{get_segment, has_filter, has_function, locate, Builtin, Context, Deserializer, Error, Expression, Fallback, Format, Node, Origin, Part, Result, Segment, ToDeserializable};
+
+/// A node of a dependency graph.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Dependency {
+    /// A template value, by its dotted path, like `servers.0.host`.
+    Key {
+        /// The file the template was read from, if any.
+        file: Option<PathBuf>,
+        /// The value path. Directives, like `$if` and `$for`, are part of the value containing them.
+        path: String
+    },
+    /// A context data value, by its dotted path.
+    Data(String),
+    /// An included file.
+    File(PathBuf),
+    /// An environment variable.
+    Environment(String),
+    /// A custom function.
+    Function(String)
+}
+
+impl Dependency {
+    /// Whether changing one of the nodes changes the other, like `data` and `data.age`.
+    fn overlaps(&self, other: &Self) -> bool {
+        let overlaps = |path: &str, other: &str| {
+            let (short, long) = if path.len() <= other.len() { (path, other) } else { (other, path) };
+            short.is_empty() || long == short || long.starts_with(&format!("{}.", short))
+        };
+        match (self, other) {
+            (Self::Key { file, path }, Self::Key { file: other_file, path: other_path }) => file == other_file && overlaps(path, other_path),
+            (Self::Data(path), Self::Data(other)) => overlaps(path, other),
+            (node, other) => node == other
+        }
+    }
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Key { file: Some(file), path } => write!(f, "{}#{}", file.display(), path),
+            Self::Key { file: None, path } if path.is_empty() => write!(f, "."),
+            Self::Key { file: None, path } => write!(f, "{}", path),
+            Self::Data(path) => write!(f, "data:{}", path),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Environment(name) => write!(f, "env:{}", name),
+            Self::Function(name) => write!(f, "function:{}", name)
+        }
+    }
+}
+
+/// Dependencies between template values, context data, files, environment variables and functions.
+/// Edges go from a node to the nodes it depends on.
+#[derive(Debug, Default, Clone)]
+pub struct Graph {
+    /// Nodes with the nodes they depend on.
+    edges: BTreeMap<Dependency, BTreeSet<Dependency>>,
+    /// Nodes with the nodes depending on them.
+    dependents: BTreeMap<Dependency, BTreeSet<Dependency>>
+}
+
+impl Graph {
+    /// Get every node.
+    pub fn nodes(&self) -> impl Iterator<Item = &Dependency> {
+        self.edges.keys()
+    }
+
+    /// Get the nodes `node` directly depends on.
+    pub fn dependencies<'a>(&'a self, node: &Dependency) -> impl Iterator<Item = &'a Dependency> {
+        self.edges.get(node).into_iter().flatten()
+    }
+
+    /// Get the nodes directly depending on `node`.
+    pub fn dependents<'a>(&'a self, node: &Dependency) -> impl Iterator<Item = &'a Dependency> {
+        self.dependents.get(node).into_iter().flatten()
+    }
+
+    /// Get every node affected by a change to `node`, directly or not.
+    /// Changing a path also changes the paths inside it and the path containing it, like `data.age` and `data`.
+    pub fn affected_by(&self, node: &Dependency) -> BTreeSet<&Dependency> {
+        let mut affected = BTreeSet::new();
+        let mut pending = self.nodes().filter(|other| other.overlaps(node)).collect::<Vec<_>>();
+        while let Some(node) = pending.pop() {
+            for dependent in self.dependents(node) {
+                if affected.insert(dependent) {
+                    pending.push(dependent);
+                }
+            }
+        }
+        affected
+    }
+
+    /// Get every node after the nodes it depends on, failing if they depend on each other in a cycle.
+    pub fn topological_order(&self) -> Result<Vec<&Dependency>> {
+        let mut order = Vec::with_capacity(self.edges.len());
+        let mut ordered = BTreeSet::new();
+        for root in self.nodes() {
+            if ordered.contains(root) {
+                continue
+            }
+            // Nodes being visited, from the root, with their dependencies left to visit.
+            let mut path = vec![(root, self.dependencies(root))];
+            let mut visiting = BTreeSet::from([root]);
+            while let Some((node, dependencies)) = path.last_mut() {
+                match dependencies.next() {
+                    Some(dependency) if ordered.contains(dependency) => {},
+                    Some(dependency) if visiting.contains(dependency) => {
+                        let index = path.iter().position(|(node, _)| *node == dependency).unwrap_or_default();
+                        let chain = path[index ..].iter().map(|(node, _)| *node).chain([dependency]).map(|node| node.to_string()).collect();
+                        return Err(Error::Cycle { chain })
+                    },
+                    Some(dependency) => {
+                        visiting.insert(dependency);
+                        path.push((dependency, self.dependencies(dependency)));
+                    },
+                    None => {
+                        let node = *node;
+                        path.pop();
+                        visiting.remove(node);
+                        ordered.insert(node);
+                        order.push(node);
+                    }
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Export the graph in the Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let quote = |node: &Dependency| format!("\"{}\"", node.to_string().replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph dependencies {\n");
+        for node in self.nodes() {
+            let shape = match node {
+                Dependency::Key { .. } => "ellipse",
+                Dependency::Data(_) => "box",
+                Dependency::File(_) => "note",
+                Dependency::Environment(_) => "diamond",
+                Dependency::Function(_) => "hexagon"
+            };
+            dot.push_str(&format!("    {} [shape={}];\n", quote(node), shape));
+        }
+        for (node, dependencies) in &self.edges {
+            for dependency in dependencies {
+                dot.push_str(&format!("    {} -> {};\n", quote(node), quote(dependency)));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn add_node(&mut self, node: Dependency) {
+        self.edges.entry(node).or_default();
+    }
+
+    fn add_edge(&mut self, node: Dependency, dependency: Dependency) {
+        self.add_node(dependency.clone());
+        self.dependents.entry(dependency.clone()).or_default().insert(node.clone());
+        self.edges.entry(node).or_default().insert(dependency);
+    }
+}
+
+/// Builds a dependency graph without rendering.
+pub(crate) struct GraphBuilder<'a> {
+    deserializer: &'a Deserializer,
+    graph: Graph,
+    /// Files already added.
+    files: BTreeSet<PathBuf>
+}
+
+/// A template being added to the graph.
+struct Document<'a> {
+    file: Option<PathBuf>,
+    value: &'a Value,
+    context: Context,
+    /// Paths of the values with placeholders.
+    leaves: BTreeSet<String>
+}
+
+/// A placeholder of a compiled template, with the path of its value and the loop variables in its scope.
+struct Reference<'a> {
+    path: String,
+    expression: &'a Expression,
+    variables: Vec<&'a str>
+}
+
+impl<'a> GraphBuilder<'a> {
+    pub(crate) fn new(deserializer: &'a Deserializer) -> Self {
+        Self { deserializer, graph: Graph::default(), files: BTreeSet::new() }
+    }
+
+    pub(crate) fn finish(self) -> Graph {
+        self.graph
+    }
+
+    /// Add a template and the files it includes.
+    pub(crate) fn add_document(&mut self, file: Option<PathBuf>, value: Value, context: &Context) -> Result<()> {
+        self.files.extend(file.clone());
+        let root = Node::compile(&value, self.deserializer.syntax(), Origin::Template)?;
+        let mut references = Vec::new();
+        collect(&root, "", &[], &mut references);
+        let value = Arc::new(value);
+        let context = self.deserializer.prepare_context(context, file.clone(), value.clone())?;
+        let leaves = references.iter().map(|reference| reference.path.clone()).collect();
+        let document = Document { file: file.clone(), value: &value, context, leaves };
+        for reference in &references {
+            let node = Dependency::Key { file: file.clone(), path: reference.path.clone() };
+            self.graph.add_node(node.clone());
+            self.add_expression(&document, &node, reference.expression, &reference.variables)?;
+        }
+        if let Some(file) = file {
+            self.graph.add_node(Dependency::File(file.clone()));
+            for path in &document.leaves {
+                self.graph.add_edge(Dependency::File(file.clone()), Dependency::Key { file: Some(file.clone()), path: path.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the dependencies of a placeholder of `node`, skipping the paths of loop `variables`.
+    fn add_expression(&mut self, document: &Document, node: &Dependency, expression: &Expression, variables: &[&str]) -> Result<()> {
+        let placeholder = &expression.placeholder;
+        let functions = document.context.functions();
+        match placeholder.type_.as_deref() {
+            None => self.add_reference(document, node, &expression.segments, variables)?,
+            Some(type_) if !has_function(&document.context, type_) => return Err(Error::FunctionNotFound { name: type_.to_string() }),
+            Some(type_) => {
+                let syntax = placeholder.syntax();
+                let placeholders = syntax.placeholders(&expression.path)?;
+                match functions.builtin(type_) {
+                    Some(Builtin::String) => self.add_reference(document, node, &Segment::compile(&placeholder.path(), Origin::Template)?, variables)?,
+                    Some(Builtin::Include(format)) if placeholders.is_empty() => {
+                        let format = format.unwrap_or_else(|| Format::from_path(std::path::Path::new(&expression.path)));
+                        self.add_include(document, node, &expression.path, format)?;
+                    },
+                    Some(Builtin::Env) if placeholders.is_empty() => {
+                        let name = expression.path.split_once(syntax.function_separator()).map_or(expression.path.as_str(), |(_, name)| name);
+                        self.graph.add_edge(node.clone(), Dependency::Environment(name.to_string()));
+                    },
+                    builtin => {
+                        if builtin.is_none() {
+                            self.graph.add_edge(node.clone(), Dependency::Function(type_.to_string()));
+                        }
+                        // Arguments with placeholders depend on them.
+                        for placeholder in placeholders {
+                            self.add_expression(document, node, &Expression::new(placeholder, Origin::Template)?, variables)?;
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(Fallback::Template(fallback)) = &expression.fallback {
+            let mut references = Vec::new();
+            collect(fallback, "", variables, &mut references);
+            for reference in references {
+                self.add_expression(document, node, reference.expression, &reference.variables)?;
+            }
+        }
+        for filter in &expression.filters {
+            if !has_filter(&document.context, filter) {
+                return Err(Error::FunctionNotFound { name: filter.clone() })
+            }
+            if !functions.is_builtin_filter(filter) {
+                self.graph.add_edge(node.clone(), Dependency::Function(filter.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the dependency of `node` on a path, looked up in the data and then in the document.
+    fn add_reference(&mut self, document: &Document, node: &Dependency, segments: &[Segment], variables: &[&str]) -> Result<()> {
+        let mut keys = Vec::new();
+        for segment in segments {
+            match segment {
+                Segment::Key(key) => keys.push(key.as_str()),
+                Segment::Placeholder(expression) => {
+                    // The rest of the path is only known when rendering.
+                    self.add_expression(document, node, expression, variables)?;
+                    break
+                }
+            }
+        }
+        let name = |keys: &[&str]| keys.iter().map(|key| key.trim_start_matches('[').trim_end_matches(']')).collect::<Vec<_>>().join(".");
+        match keys.first() {
+            None => return Ok(()),
+            Some(first) if first.starts_with('$') || variables.contains(first) => return Ok(()),
+            Some(_) => {}
+        }
+        if lookup(document.context.data(), &keys).is_some() {
+            self.graph.add_edge(node.clone(), Dependency::Data(name(&keys)));
+            return Ok(())
+        }
+        match (1 ..= keys.len()).rev().find(|length| lookup(document.value, &keys[.. *length]).is_some()) {
+            Some(length) => {
+                let path = name(&keys[.. length]);
+                let key = Dependency::Key { file: document.file.clone(), path: path.clone() };
+                if key != *node {
+                    self.graph.add_edge(node.clone(), key.clone());
+                }
+                // Values containing templates depend on them.
+                let prefix = if path.is_empty() { path.clone() } else { format!("{}.", path) };
+                let leaves = document.leaves.range(prefix.clone() ..).take_while(|leaf| leaf.starts_with(&prefix));
+                for leaf in leaves.filter(|leaf| **leaf != path) {
+                    self.graph.add_edge(key.clone(), Dependency::Key { file: document.file.clone(), path: leaf.clone() });
+                }
+            },
+            None => self.graph.add_edge(node.clone(), Dependency::Data(name(&keys)))
+        }
+        Ok(())
+    }
+
+    /// Add the dependency of `node` on an included file, and the file itself once.
+    /// Files that can't be found are added as written, other errors, like sandbox denials, fail.
+    fn add_include(&mut self, document: &Document, node: &Dependency, path: &str, format: Format) -> Result<()> {
+        let file = match locate(&document.context, path) {
+            Ok(file) => file,
+            Err(error) if error.is_not_found() => {
+                self.graph.add_edge(node.clone(), Dependency::File(PathBuf::from(path)));
+                return Ok(())
+            },
+            Err(error) => return Err(error)
+        };
+        let file = document.context.file_system().canonicalize(&file).unwrap_or(file);
+        self.graph.add_edge(node.clone(), Dependency::File(file.clone()));
+        if !self.files.insert(file.clone()) {
+            return Ok(())
+        }
+        let (_, value) = (file.as_path(), format).to_deserializable(self.deserializer, &document.context)?;
+        self.add_document(Some(file), value, &document.context)
+    }
+}
+
+/// Collect the placeholders of a compiled value at `path`, with the loop variables in their scope.
+/// Directives, like `$if` and `$for`, are part of the value containing them.
+fn collect<'a>(node: &'a Node, path: &str, variables: &[&'a str], references: &mut Vec<Reference<'a>>) {
+    let mut add = |expression: &'a Expression| references.push(Reference { path: path.to_string(), expression, variables: variables.to_vec() });
+    match node {
+        Node::Literal(_) | Node::Omit => {},
+        Node::Placeholder(expression) => add(expression),
+        Node::Text { parts, .. } => {
+            for part in parts {
+                if let Part::Placeholder(expression) = part {
+                    add(expression);
+                }
+            }
+        },
+        Node::Array(nodes) => {
+            for (index, node) in nodes.iter().enumerate() {
+                collect(node, &join(path, &index.to_string()), variables, references);
+            }
+        },
+        Node::Object(entries) => {
+            for (key, node) in entries {
+                collect(node, &join(path, key), variables, references);
+            }
+        },
+        Node::Conditional { condition, then, otherwise } => {
+            for node in [Some(condition), then.as_ref(), otherwise.as_ref()].into_iter().flatten() {
+                collect(node, path, variables, references);
+            }
+        },
+        Node::Loop(loop_) => {
+            collect(&loop_.items, path, variables, references);
+            collect(&loop_.body, path, &[variables, &[loop_.variable.as_str()]].concat(), references);
+        }
+    }
+}
+
+/// Append a key to a dotted path.
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Get the value at a path without resolving it.
+fn lookup<'a>(value: &'a Value, segments: &[&str]) -> Option<Cow<'a, Value>> {
+    segments.iter().try_fold(Cow::Borrowed(value), |value, segment| match value {
+        Cow::Borrowed(value) => get_segment(value, segment),
+        Cow::Owned(value) => get_segment(&value, segment).map(|value| Cow::Owned(value.into_owned()))
+    })
+}
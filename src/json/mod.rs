@@ -7,6 +7,10 @@ pub trait JSON {
     /// Text without quotes (specially for strings).
     fn to_text(&self) -> String;
 
+    /// Whether the value counts as true in conditions.
+    /// `null`, `false`, `0`, `""`, `[]` and `{}` are false, everything else is true.
+    fn is_truthy(&self) -> bool;
+
     /// Add a value recursively.
     fn add_recursive(&mut self, new_value: Value);
 
@@ -23,6 +27,17 @@ impl JSON for Value {
         }
     }
 
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+            Value::String(string) => !string.is_empty(),
+            Value::Array(array) => !array.is_empty(),
+            Value::Object(object) => !object.is_empty()
+        }
+    }
+
     fn add_recursive(&mut self, new_value: Value) {
         let value = self;
         match value {
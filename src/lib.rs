@@ -2,17 +2,35 @@
 #![deny(missing_docs)]
 
 mod traits;
+mod format;
 mod placeholder;
 mod json;
 mod deserializer;
+mod template;
+mod cache;
+mod sandbox;
+mod file_system;
+mod analysis;
+mod graph;
+mod syntax;
+pub mod error;
 pub mod path;
 pub mod functions;
 pub mod context;
 
 use traits::*;
+use format::*;
 pub use path::*;
 pub use placeholder::*;
 pub use json::*;
 pub use functions::*;
 pub use deserializer::*;
-pub use context::*;
\ No newline at end of file
+pub use template::*;
+pub use cache::*;
+pub use sandbox::*;
+pub use file_system::*;
+pub use analysis::*;
+pub use graph::*;
+pub use syntax::*;
+pub use context::*;
+pub use error::*;
\ No newline at end of file
@@ -1,14 +1,35 @@
 //! Path module.
 
+use crate::Syntax;
+
 /// The placeholder Path.
 pub struct Path<'a> {
-    path: &'a str
+    path: &'a str,
+    syntax: Syntax
 }
 
 impl<'a> Path<'a> {
     /// Create a new Path.
     pub fn new(path: &'a str) -> Self {
-        Self { path }
+        let syntax = Syntax::default();
+        Self { path, syntax }
+    }
+
+    /// Set the syntax of the placeholders and separators in the Path.
+    pub fn with_syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Set the syntax of the placeholders and separators in the Path.
+    pub fn set_syntax(&mut self, syntax: Syntax) -> &mut Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Get the syntax of the Path.
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
     }
 
     /// Get the value of the Path.
@@ -21,24 +42,54 @@ impl<'a> Path<'a> {
     /// "{time:5}".segments() == ["time:5"].
     /// "{time:5}.time".segments() == ["time:5", "time"].
     /// "{file:file.json}.data" == ["file:file.json", "data"]. 
+    /// "servers[0].host" == ["servers", "[0]", "host"].
+    /// "servers[1:3]" == ["servers", "[1:3]"].
     pub fn segments(&self) -> Vec<&str> {
-        let mut level = 0;
+        let path = self.str();
         let mut current_segment_start = 0;
-        let mut current_segment_end = 0;
+        let mut after_bracket = false;
         let mut segments = Vec::new();
-        for character in self.str().chars() {
+        for (index, character) in self.syntax.top_level(path) {
             match character {
-                '{' => level += 1,
-                '}' => level -= 1,
-                '.' => if level == 0 {
-                        segments.push(&self.str()[current_segment_start .. current_segment_end]);
-                        current_segment_start = current_segment_end + 1;
+                '[' => {
+                    if current_segment_start < index {
+                        segments.push(&path[current_segment_start .. index]);
+                    }
+                    current_segment_start = index;
+                },
+                ']' => {
+                    segments.push(&path[current_segment_start ..= index]);
+                    current_segment_start = index + 1;
+                    after_bracket = true;
+                },
+                separator if separator == self.syntax.path_separator() => {
+                    if !after_bracket {
+                        segments.push(&path[current_segment_start .. index]);
+                    }
+                    current_segment_start = index + separator.len_utf8();
+                    after_bracket = false;
                 },
                 _ => {}
             }
-            current_segment_end += 1;
         }
-        segments.push(&self.str()[current_segment_start .. current_segment_end]);
+        if !after_bracket || current_segment_start < path.len() {
+            segments.push(&path[current_segment_start ..]);
+        }
         segments
     }
-}
\ No newline at end of file
+
+    /// Append a key or index to a JSON pointer, like "/servers" + "0" == "/servers/0".
+    pub(crate) fn pointer(parent: &str, token: &str) -> String {
+        format!("{}/{}", parent, token.replace('~', "~0").replace('/', "~1"))
+    }
+
+    /// Turn a JSON pointer into a path, like "/servers/0" == "servers.0" with `.` as the separator.
+    pub(crate) fn from_pointer(pointer: &str, separator: char) -> String {
+        pointer
+            .split('/')
+            .skip(1)
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect::<Vec<_>>()
+            .join(&separator.to_string())
+    }
+}
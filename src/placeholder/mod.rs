@@ -1,77 +1,128 @@
 //! Placeholder module.
 
-use crate::Path;
+use crate::{Path, Result, Syntax};
 
 /// This struct represents a placeholder in a JSON object.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Placeholder {
     /// The placeholder value.
     pub value: String,
     /// The placeholder type.
     pub type_: Option<String>,
+    /// The placeholder syntax.
+    pub syntax: Syntax
+}
+
+/// A piece of a template string.
+#[derive(Debug, Clone)]
+pub enum Fragment {
+    /// Text outside of placeholders, still escaped.
+    Text(String),
+    /// A placeholder.
+    Placeholder(Placeholder)
 }
 
 impl Placeholder {
     /// Get all the placeholders in a string.
     /// If value == "{time} {time:3}", then placeholders == ["{time}", "{time:3}"].
     /// If value == "{time:{time:5}}  {time}", then placeholders == ["{time:{time:5}}", "{time}"].
-    pub fn placeholders(value: &str) -> Vec<Self> {
-        let mut levels = 0;
-        let mut current_placeholder = String::new();
-        let mut placeholders = Vec::new();
-        for character in value.chars() {
-            match character {
-                '{' => {
-                    if levels == 0 {
-                        current_placeholder.clear();
-                    }
-                    levels += 1;
-                    current_placeholder.push(character);
-                }
-                '}' => {
-                    levels -= 1;
-                    current_placeholder.push(character);
-                    if levels == 0 {
-                        placeholders.push(Self::from_str(&current_placeholder).expect("Failed to create placeholder."));
-                    }
-                }
-                _ => {
-                    if levels > 0 {
-                        current_placeholder.push(character);
-                    }
-                }
-            }
-        }
-        placeholders
+    /// Unbalanced braces, like in "a } b" or "{time", are reported as `Error::Syntax`.
+    pub fn placeholders(value: &str) -> Result<Vec<Self>> {
+        Syntax::default().placeholders(value)
+    }
+
+    /// Split a string into text and placeholders.
+    /// If value == "Hi, {name}!", then fragments == [Text("Hi, "), Placeholder("{name}"), Text("!")].
+    /// Escaped braces, like in `\{name\}`, are kept as text.
+    pub fn fragments(value: &str) -> Result<Vec<Fragment>> {
+        Syntax::default().fragments(value)
+    }
+
+    /// Escape a text so it can be embedded in a template without being resolved.
+    /// Example: `{a}` is escaped as `\{a\}`.
+    pub fn escape(text: &str) -> String {
+        Syntax::default().escape(text)
+    }
+
+    /// Unescape a text, turning `\{` and `\}` into `{` and `}`, and `\\` before a brace into `\`.
+    pub fn unescape(text: &str) -> String {
+        Syntax::default().unescape(text)
     }
 
     /// Create a new placeholder from a string.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(value: &str) -> Option<Self> {
-        if value.starts_with('{') && value.ends_with('}') {
-            let value = value.to_string();
-            let chars = value.chars();
-            let first = chars.clone().nth(0);
-            let second = chars.clone().nth(1);
-            let type_ = first.zip(second).map(|(first, second)| {
-                if first == '{' && second.is_alphanumeric() {
-                    value.find(':').map(|index| value[1 .. index].to_string())
-                } else {
-                    None
-                }
-            }).flatten();
-            Some(Self { value, type_ })
-        } else {
-            None
-        }
+        Syntax::default().placeholder(value)
+    }
+
+    /// Get the syntax of the placeholder.
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
     }
 
     /// Get the path of the placeholder.
-    pub fn path(&self) -> Path {
-        if let Some(type_) = &self.type_ {
-            Path::new(&self.value[type_.len() + 2 .. self.value.len() - 1])
+    pub fn path(&self) -> Path<'_> {
+        Path::new(self.split_fallback(self.expression()).0).with_syntax(self.syntax)
+    }
+
+    /// Get the fallback of the placeholder, used when its value can't be found.
+    /// If value == "{db.port ?? 5432}", then fallback == Some("5432").
+    pub fn fallback(&self) -> Option<&str> {
+        self.split_fallback(self.expression()).1
+    }
+
+    /// Get the filters the placeholder value is piped through, in order.
+    /// If value == "{user.name | upper | trim}", then filters == ["upper", "trim"].
+    pub fn filters(&self) -> Vec<&str> {
+        self.split_pipes(self.body()).into_iter().skip(1).collect()
+    }
+
+    /// The placeholder body without its filters.
+    fn expression(&self) -> &str {
+        self.split_pipes(self.body())[0]
+    }
+
+    /// The placeholder without its delimiters.
+    pub(crate) fn inner(&self) -> &str {
+        &self.value[self.syntax.open().len() .. self.value.len() - self.syntax.close().len()]
+    }
+
+    /// The placeholder without its delimiters and type.
+    fn body(&self) -> &str {
+        match &self.type_ {
+            Some(type_) => &self.inner()[type_.len() + self.syntax.function_separator().len_utf8() ..],
+            None => self.inner()
+        }
+    }
+
+    /// Split "expression | filter | filter" at the top level "|"s.
+    fn split_pipes<'a>(&self, body: &'a str) -> Vec<&'a str> {
+        let mut start = 0;
+        let mut parts = Vec::new();
+        for (index, character) in self.syntax.top_level(body) {
+            if character == '|' {
+                parts.push(body[start .. index].trim());
+                start = index + 1;
+            }
+        }
+        if parts.is_empty() {
+            parts.push(body);
         } else {
-            Path::new(&self.value[1 .. self.value.len() - 1])
+            parts.push(body[start ..].trim());
         }
+        parts
     }
-}
 
+    /// Split "path ?? fallback" at the first top level "??".
+    fn split_fallback<'a>(&self, body: &'a str) -> (&'a str, Option<&'a str>) {
+        let characters = self.syntax.top_level(body);
+        for pair in characters.windows(2) {
+            if let [(index, '?'), (next, '?')] = pair {
+                if *next == index + 1 {
+                    return (body[.. *index].trim_end(), Some(body[index + 2 ..].trim()))
+                }
+            }
+        }
+        (body, None)
+    }
+}
@@ -0,0 +1,85 @@
+//! Sandbox module.
+
+use std::path::{Path, PathBuf};
+
+use crate::{absolute, Error, FileSystem, Result};
+
+/// How a sandbox treats symbolic links.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Refuse paths going through symbolic links inside the allowed roots.
+    Deny,
+    /// Follow symbolic links only if their target is inside an allowed root.
+    #[default]
+    WithinRoots,
+    /// Follow symbolic links anywhere, checking only the path as written.
+    Follow
+}
+
+/// Confines included files to allowed root directories.
+#[derive(Debug, Default, Clone)]
+pub struct Sandbox {
+    /// Allowed root directories.
+    roots: Vec<PathBuf>,
+    /// Symbolic links policy.
+    symlinks: SymlinkPolicy
+}
+
+impl Sandbox {
+    /// Create a sandbox allowing files inside `roots`.
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots, ..Default::default() }
+    }
+
+    /// Get the allowed root directories.
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Set the symbolic links policy.
+    pub fn with_symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Set the symbolic links policy.
+    pub fn set_symlinks(&mut self, symlinks: SymlinkPolicy) -> &mut Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Get the symbolic links policy.
+    pub fn symlinks(&self) -> SymlinkPolicy {
+        self.symlinks
+    }
+
+    /// Check that `path` can be accessed in `file_system`, returning the path to read.
+    /// The path must exist; `..` components are resolved before checking, and symbolic links according to the policy.
+    pub fn check(&self, path: &Path, file_system: &dyn FileSystem) -> Result<PathBuf> {
+        let denied = |message: &str| Err(Error::Sandbox { path: path.to_path_buf(), message: message.to_string() });
+        let canonical = file_system.canonicalize(path).map_err(|source| Error::Io { path: path.to_path_buf(), source })?;
+        let lexical = absolute(path);
+        let roots = self
+            .roots
+            .iter()
+            .flat_map(|root| [Some(absolute(root)), file_system.canonicalize(root).ok()])
+            .flatten()
+            .collect::<Vec<_>>();
+        let Some(root) = roots.iter().find(|root| lexical.starts_with(root)) else {
+            return denied("outside of the allowed directories")
+        };
+        match self.symlinks {
+            // The checked path is read, so `..` can't step out of a symbolic link.
+            SymlinkPolicy::Follow => Ok(lexical),
+            SymlinkPolicy::Deny => {
+                let symlink = lexical
+                    .ancestors()
+                    .take_while(|ancestor| ancestor != root)
+                    .any(|ancestor| file_system.is_symlink(ancestor));
+                if symlink { denied("symbolic links aren't allowed") } else { Ok(lexical) }
+            },
+            SymlinkPolicy::WithinRoots if roots.iter().any(|root| canonical.starts_with(root)) => Ok(canonical),
+            SymlinkPolicy::WithinRoots => denied("symbolic link to outside of the allowed directories")
+        }
+    }
+}
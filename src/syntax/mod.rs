@@ -0,0 +1,342 @@
+//! Syntax module.
+
+use crate::{Error, Fragment, Placeholder, Result};
+
+/// Placeholder syntax: delimiters, function separator and path separator.
+/// The default is `{function:path.to.value}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Syntax {
+    /// Opening delimiter.
+    open: Delimiter,
+    /// Closing delimiter.
+    close: Delimiter,
+    /// Separator between a function name and its argument.
+    function_separator: char,
+    /// Separator between path segments.
+    path_separator: char
+}
+
+/// A placeholder delimiter, stored inline so syntaxes stay `Copy` without borrowing their delimiters.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Delimiter {
+    bytes: [u8; Delimiter::CAPACITY],
+    len: usize
+}
+
+impl Delimiter {
+    /// Maximum length of a delimiter, in bytes.
+    const CAPACITY: usize = 8;
+
+    fn new(text: &str) -> Option<Self> {
+        let mut bytes = [0; Self::CAPACITY];
+        bytes.get_mut(.. text.len())?.copy_from_slice(text.as_bytes());
+        Some(Self { bytes, len: text.len() })
+    }
+
+    fn as_str(&self) -> &str {
+        // The bytes are copied from a `str`.
+        std::str::from_utf8(&self.bytes[.. self.len]).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Debug for Delimiter {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(formatter)
+    }
+}
+
+impl Default for Syntax {
+    fn default() -> Self {
+        Self::new("{", "}").expect("Valid default delimiters.")
+    }
+}
+
+impl Syntax {
+    /// Create a syntax with placeholders delimited by `open` and `close`, like `${` and `}`, `{{` and `}}` or `<%` and `%>`.
+    /// Delimiters can't be empty, equal, longer than 8 bytes or start with `\`.
+    pub fn new(open: &str, close: &str) -> Result<Self> {
+        let invalid = || Error::custom(format!("Invalid placeholder delimiters {:?} and {:?}", open, close));
+        if open.is_empty() || close.is_empty() || open == close || open.starts_with('\\') || close.starts_with('\\') {
+            return Err(invalid())
+        }
+        let open = Delimiter::new(open).ok_or_else(invalid)?;
+        let close = Delimiter::new(close).ok_or_else(invalid)?;
+        Ok(Self { open, close, function_separator: ':', path_separator: '.' })
+    }
+
+    /// Get the opening delimiter.
+    pub fn open(&self) -> &str {
+        self.open.as_str()
+    }
+
+    /// Get the closing delimiter.
+    pub fn close(&self) -> &str {
+        self.close.as_str()
+    }
+
+    /// Set the separator between a function name and its argument, `:` by default.
+    /// It must differ from the path separator and can't be a character with another meaning in placeholders.
+    pub fn with_function_separator(mut self, function_separator: char) -> Result<Self> {
+        self.set_function_separator(function_separator)?;
+        Ok(self)
+    }
+
+    /// Set the separator between a function name and its argument, `:` by default.
+    /// It must differ from the path separator and can't be a character with another meaning in placeholders.
+    pub fn set_function_separator(&mut self, function_separator: char) -> Result<&mut Self> {
+        self.check_separator(function_separator, self.path_separator)?;
+        self.function_separator = function_separator;
+        Ok(self)
+    }
+
+    /// Get the separator between a function name and its argument.
+    pub fn function_separator(&self) -> char {
+        self.function_separator
+    }
+
+    /// Set the separator between path segments, `.` by default.
+    /// It must differ from the function separator and can't be a character with another meaning in placeholders.
+    pub fn with_path_separator(mut self, path_separator: char) -> Result<Self> {
+        self.set_path_separator(path_separator)?;
+        Ok(self)
+    }
+
+    /// Set the separator between path segments, `.` by default.
+    /// It must differ from the function separator and can't be a character with another meaning in placeholders.
+    pub fn set_path_separator(&mut self, path_separator: char) -> Result<&mut Self> {
+        self.check_separator(path_separator, self.function_separator)?;
+        self.path_separator = path_separator;
+        Ok(self)
+    }
+
+    /// Get the separator between path segments.
+    pub fn path_separator(&self) -> char {
+        self.path_separator
+    }
+
+    /// Check that a separator isn't the other separator, a delimiter character or a character used by paths, fallbacks, filters or escapes.
+    fn check_separator(&self, separator: char, other: char) -> Result<()> {
+        let reserved = matches!(separator, '|' | '?' | '[' | ']' | '\\' | '_' | '-')
+            || separator.is_alphanumeric()
+            || separator.is_whitespace()
+            || separator == other
+            || self.open().contains(separator)
+            || self.close().contains(separator);
+        if reserved {
+            return Err(Error::custom(format!("Invalid placeholder separator {:?}", separator)))
+        }
+        Ok(())
+    }
+
+    /// Get all the placeholders in a string.
+    pub fn placeholders(&self, value: &str) -> Result<Vec<Placeholder>> {
+        let placeholders = self
+            .fragments(value)?
+            .into_iter()
+            .filter_map(|fragment| match fragment {
+                Fragment::Placeholder(placeholder) => Some(placeholder),
+                Fragment::Text(_) => None
+            })
+            .collect();
+        Ok(placeholders)
+    }
+
+    /// Split a string into text and placeholders.
+    /// An unclosed placeholder is reported as `Error::Syntax`, and so is a stray closing delimiter if the opening one is a single character.
+    /// Longer opening delimiters leave single characters free for text, so `${name}/{id}` is a placeholder followed by `/{id}`.
+    pub fn fragments(&self, value: &str) -> Result<Vec<Fragment>> {
+        let mut levels = 0;
+        let mut start = 0;
+        let mut fragments = Vec::new();
+        for (index, token) in self.tokens(value) {
+            match token {
+                Token::Open => {
+                    if levels == 0 {
+                        if start < index {
+                            fragments.push(Fragment::Text(value[start .. index].to_string()));
+                        }
+                        start = index;
+                    }
+                    levels += 1;
+                },
+                Token::Close if levels == 0 => {
+                    if self.open().chars().count() == 1 {
+                        return Err(Error::syntax(value, format!("Unexpected '{}' at position {}", self.close(), index)));
+                    }
+                },
+                Token::Close => {
+                    levels -= 1;
+                    if levels == 0 {
+                        let end = index + self.close.len;
+                        let placeholder = self
+                            .placeholder(&value[start .. end])
+                            .ok_or_else(|| Error::syntax(value, format!("Invalid placeholder at position {}", start)))?;
+                        fragments.push(Fragment::Placeholder(placeholder));
+                        start = end;
+                    }
+                },
+                Token::Character(_) => {}
+            }
+        }
+        if levels > 0 {
+            return Err(Error::syntax(value, format!("Unclosed '{}' at position {}", self.open(), start)));
+        }
+        if start < value.len() {
+            fragments.push(Fragment::Text(value[start ..].to_string()));
+        }
+        Ok(fragments)
+    }
+
+    /// Create a placeholder from a string, like `{file:a.json}`.
+    pub fn placeholder(&self, value: &str) -> Option<Placeholder> {
+        let body = value.strip_prefix(self.open())?.strip_suffix(self.close())?;
+        let type_ = body
+            .chars()
+            .next()
+            .filter(|first| first.is_alphanumeric())
+            .and_then(|_| body.find(self.function_separator))
+            .map(|index| &body[.. index])
+            .filter(|name| name.chars().all(|character| character.is_alphanumeric() || character == '_' || character == '-'))
+            .map(|name| name.to_string());
+        Some(Placeholder { value: value.to_string(), type_, syntax: *self })
+    }
+
+    /// Escape a text so it can be embedded in a template without being resolved, by prefixing delimiters with `\`.
+    /// Runs of `\` before delimiters are doubled, other `\` are kept as they are.
+    pub fn escape(&self, text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(character) = rest.chars().next() {
+            let run = rest.len() - rest.trim_start_matches('\\').len();
+            match self.delimiter_at(&rest[run ..]) {
+                Some(delimiter) => {
+                    escaped.extend(std::iter::repeat_n('\\', run * 2 + 1));
+                    escaped.push_str(delimiter);
+                    rest = &rest[run + delimiter.len() ..];
+                },
+                None if run > 0 => {
+                    escaped.push_str(&rest[.. run]);
+                    rest = &rest[run ..];
+                },
+                None => {
+                    escaped.push(character);
+                    rest = &rest[character.len_utf8() ..];
+                }
+            }
+        }
+        escaped
+    }
+
+    /// Unescape a text. A run of `\` before a delimiter is halved, and the delimiter is literal if the run is odd, so `\{` is `{` and `\\{` is `\` followed by a placeholder.
+    /// Other `\` are kept as they are.
+    pub fn unescape(&self, text: &str) -> String {
+        self.unescape_text(text, false)
+    }
+
+    /// Unescape a text fragment, which is followed by a placeholder if `before_placeholder` is set.
+    pub(crate) fn unescape_text(&self, text: &str, before_placeholder: bool) -> String {
+        let mut unescaped = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('\\') {
+            unescaped.push_str(&rest[.. start]);
+            let run = rest[start ..].len() - rest[start ..].trim_start_matches('\\').len();
+            rest = &rest[start + run ..];
+            match self.delimiter_at(rest) {
+                Some(delimiter) => {
+                    unescaped.extend(std::iter::repeat_n('\\', run / 2));
+                    if run % 2 == 1 {
+                        unescaped.push_str(delimiter);
+                        rest = &rest[delimiter.len() ..];
+                    }
+                },
+                None if rest.is_empty() && before_placeholder => unescaped.extend(std::iter::repeat_n('\\', run / 2)),
+                None => unescaped.extend(std::iter::repeat_n('\\', run))
+            }
+        }
+        unescaped.push_str(rest);
+        unescaped
+    }
+
+    /// Get the unescaped characters outside of placeholders, with their position.
+    pub(crate) fn top_level(&self, text: &str) -> Vec<(usize, char)> {
+        let mut levels = 0;
+        let mut characters = Vec::new();
+        for (index, token) in self.tokens(text) {
+            match token {
+                Token::Open => levels += 1,
+                Token::Close if levels > 0 => levels -= 1,
+                Token::Close => characters.extend(self.close().char_indices().map(|(offset, character)| (index + offset, character))),
+                Token::Character(character) if levels == 0 => characters.push((index, character)),
+                Token::Character(_) => {}
+            }
+        }
+        characters
+    }
+
+    /// Split a text into delimiters and unescaped characters, with their position.
+    /// Inside placeholders, brackets matching the closing delimiter nest, so `${a ?? {"b": 1}}` ends at the last `}`.
+    fn tokens(&self, text: &str) -> Vec<(usize, Token)> {
+        let bracket = self.bracket();
+        let mut tokens = Vec::new();
+        // Open brackets in each placeholder being scanned.
+        let mut brackets: Vec<usize> = Vec::new();
+        let mut index = 0;
+        while let Some(character) = text[index ..].chars().next() {
+            let rest = &text[index ..];
+            if character == '\\' {
+                let run = rest.len() - rest.trim_start_matches('\\').len();
+                tokens.extend((index .. index + run).map(|index| (index, Token::Character('\\'))));
+                index += run;
+                // A delimiter after an odd run is escaped and skipped.
+                if let Some(delimiter) = self.delimiter_at(&text[index ..]).filter(|_| run % 2 == 1) {
+                    index += delimiter.len();
+                }
+            } else if rest.starts_with(self.open()) {
+                tokens.push((index, Token::Open));
+                brackets.push(0);
+                index += self.open.len;
+            } else if let Some(open) = brackets.last_mut().filter(|_| bracket.is_some_and(|(opener, _)| opener == character)) {
+                *open += 1;
+                tokens.push((index, Token::Character(character)));
+                index += character.len_utf8();
+            } else if let Some(open) = brackets.last_mut().filter(|open| **open > 0 && bracket.is_some_and(|(_, closer)| closer == character)) {
+                *open -= 1;
+                tokens.push((index, Token::Character(character)));
+                index += character.len_utf8();
+            } else if rest.starts_with(self.close()) {
+                tokens.push((index, Token::Close));
+                brackets.pop();
+                index += self.close.len;
+            } else {
+                tokens.push((index, Token::Character(character)));
+                index += character.len_utf8();
+            }
+        }
+        tokens
+    }
+
+    /// Get the bracket pair of the closing delimiter, like `{` and `}` for `${` and `}`, unless its opening bracket is the opening delimiter.
+    fn bracket(&self) -> Option<(char, char)> {
+        let closer = self.close().chars().next()?;
+        let opener = match closer {
+            '}' => '{',
+            ']' => '[',
+            ')' => '(',
+            '>' => '<',
+            _ => return None
+        };
+        (self.open() != opener.encode_utf8(&mut [0; 4])).then_some((opener, closer))
+    }
+
+    /// Get the delimiter a text starts with, if any.
+    fn delimiter_at(&self, text: &str) -> Option<&str> {
+        [self.open(), self.close()].into_iter().find(|delimiter| text.starts_with(delimiter))
+    }
+}
+
+/// A piece of a text scanned for delimiters.
+enum Token {
+    Open,
+    Close,
+    Character(char)
+}
@@ -0,0 +1,319 @@
+//! Template module.
+
+use std::{path::PathBuf, sync::Arc};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::{Context, Deserializer, Error, Fragment, Path, Placeholder, Result, Syntax, ToDeserializable};
+
+/// A template compiled once to be rendered many times.
+/// Every string is split into text and placeholders, and every placeholder path into segments, only once.
+#[derive(Debug, Clone)]
+pub struct Template {
+    /// Deserializer used to render.
+    deserializer: Deserializer,
+    /// File the template was read from, if any.
+    path: Option<PathBuf>,
+    /// Template source, used to resolve self references.
+    source: Arc<Value>,
+    /// Compiled template.
+    root: Node
+}
+
+impl Template {
+    /// Compile a template.
+    pub fn compile(value: impl ToDeserializable) -> Result<Self> {
+        Deserializer::new().compile(value)
+    }
+
+    pub(crate) fn new(deserializer: Deserializer, path: Option<PathBuf>, source: Value) -> Result<Self> {
+        let root = Node::compile(&source, deserializer.syntax(), Origin::Template)?;
+        let source = Arc::new(source);
+        Ok(Self { deserializer, path, source, root })
+    }
+
+    /// Render the template.
+    pub fn render(&self, context: &Context) -> Result<Value> {
+        self.resolve(&self.deserializer, context)
+    }
+
+    /// Resolve the template in its own context.
+    pub(crate) fn resolve(&self, deserializer: &Deserializer, context: &Context) -> Result<Value> {
+        let context = deserializer.prepare_context(context, self.path.clone(), self.source.clone())?;
+        deserializer.render_document(&self.root, &context)
+    }
+
+    /// Get the template source.
+    pub(crate) fn source(&self) -> &Arc<Value> {
+        &self.source
+    }
+
+    /// Render the template and deserialize it.
+    pub fn deserialize<T: DeserializeOwned>(&self, context: &Context) -> Result<T> {
+        serde_json::from_value(self.render(context)?).map_err(Error::from)
+    }
+}
+
+/// Where a value being resolved comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Origin {
+    /// A template. Only templates can omit values, with `Deserializer::OMIT`.
+    Template,
+    /// Context data, whose strings can reference other values.
+    Data,
+    /// A value that's already resolved, like a loop variable.
+    Resolved
+}
+
+/// A compiled JSON value.
+#[derive(Debug, Clone)]
+pub(crate) enum Node {
+    /// A value without placeholders.
+    Literal(Value),
+    /// A value removed from its object or array, written `Deserializer::OMIT` in templates.
+    Omit,
+    /// A string made of a single placeholder, keeping the placeholder value type.
+    Placeholder(Box<Expression>),
+    /// A string made of text and placeholders.
+    Text {
+        /// The template string.
+        source: String,
+        /// Its parts.
+        parts: Vec<Part>
+    },
+    /// An array.
+    Array(Vec<Node>),
+    /// An object.
+    Object(Vec<(String, Node)>),
+    /// A conditional, `{"$if": condition, "$then": value, "$else": value}`.
+    Conditional {
+        /// The condition.
+        condition: Box<Node>,
+        /// Value used if the condition is truthy.
+        then: Option<Box<Node>>,
+        /// Value used otherwise.
+        otherwise: Option<Box<Node>>
+    },
+    /// A loop, `{"$for": "item in {items}", "$do": value}`.
+    Loop(Box<Loop>)
+}
+
+/// A compiled `$for` loop.
+#[derive(Debug, Clone)]
+pub(crate) struct Loop {
+    /// The `$for` expression.
+    pub(crate) expression: String,
+    /// Name of the loop variable.
+    pub(crate) variable: String,
+    /// Items iterated over.
+    pub(crate) items: Node,
+    /// Value rendered for each item.
+    pub(crate) body: Node
+}
+
+/// A part of a compiled template string.
+#[derive(Debug, Clone)]
+pub(crate) enum Part {
+    /// Text outside of placeholders, unescaped if it's from a template.
+    Text(String),
+    /// A placeholder.
+    Placeholder(Box<Expression>)
+}
+
+/// A compiled placeholder.
+#[derive(Debug, Clone)]
+pub(crate) struct Expression {
+    /// The placeholder.
+    pub(crate) placeholder: Placeholder,
+    /// Key used to detect cycles.
+    pub(crate) key: String,
+    /// The placeholder path.
+    pub(crate) path: String,
+    /// Path segments, if the placeholder isn't a function.
+    pub(crate) segments: Vec<Segment>,
+    /// Value used when the placeholder value can't be found.
+    pub(crate) fallback: Option<Fallback>,
+    /// Filters applied to the placeholder value.
+    pub(crate) filters: Vec<String>
+}
+
+/// A compiled placeholder fallback.
+#[derive(Debug, Clone)]
+pub(crate) enum Fallback {
+    /// A JSON literal.
+    Literal(Value),
+    /// A template or plain text.
+    Template(Node)
+}
+
+/// A compiled path segment.
+#[derive(Debug, Clone)]
+pub(crate) enum Segment {
+    /// An object key, array index or array slice.
+    Key(String),
+    /// A placeholder whose value replaces the value found so far.
+    Placeholder(Box<Expression>)
+}
+
+impl Node {
+    /// Compile a value.
+    pub(crate) fn compile(value: &Value, syntax: Syntax, origin: Origin) -> Result<Self> {
+        let node = match value {
+            _ if origin == Origin::Resolved => Self::Literal(value.clone()),
+            Value::String(string) => Self::string(string, syntax, origin)?,
+            Value::Array(array) => Self::array(array, syntax, origin)?,
+            Value::Object(object) => Self::object(object, syntax, origin)?,
+            value => Self::Literal(value.clone())
+        };
+        Ok(node)
+    }
+
+    /// Compile an array.
+    pub(crate) fn array(array: &[Value], syntax: Syntax, origin: Origin) -> Result<Self> {
+        let nodes = array.iter().map(|value| Self::compile(value, syntax, origin)).collect::<Result<Vec<_>>>()?;
+        if nodes.iter().all(|node| matches!(node, Self::Literal(_))) {
+            Ok(Self::Literal(Value::Array(array.to_vec())))
+        } else {
+            Ok(Self::Array(nodes))
+        }
+    }
+
+    /// Compile an object. Objects with directive keys, like `$if`, are compiled into the directive.
+    pub(crate) fn object(object: &Map<String, Value>, syntax: Syntax, origin: Origin) -> Result<Self> {
+        if object.contains_key("$if") {
+            return Self::conditional(object, syntax, origin)
+        }
+        if object.contains_key("$for") {
+            return Loop::compile(object, syntax, origin).map(|loop_| Self::Loop(Box::new(loop_)))
+        }
+        let entries = object
+            .iter()
+            .map(|(key, value)| Self::compile(value, syntax, origin).map(|node| (key.clone(), node)))
+            .collect::<Result<Vec<_>>>()?;
+        if entries.iter().all(|(_, node)| matches!(node, Self::Literal(_))) {
+            Ok(Self::Literal(Value::Object(object.clone())))
+        } else {
+            Ok(Self::Object(entries))
+        }
+    }
+
+    /// Compile `{"$if": condition, "$then": value, "$else": value}`.
+    fn conditional(object: &Map<String, Value>, syntax: Syntax, origin: Origin) -> Result<Self> {
+        Self::check_conditional(object)?;
+        let branch = |key| object.get(key).map(|value| Self::compile(value, syntax, origin).map(Box::new)).transpose();
+        Ok(Self::Conditional {
+            condition: Box::new(Self::compile(&object["$if"], syntax, origin)?),
+            then: branch("$then")?,
+            otherwise: branch("$else")?
+        })
+    }
+
+    /// Check that a conditional only has the `$if`, `$then` and `$else` keys.
+    pub(crate) fn check_conditional(object: &Map<String, Value>) -> Result<()> {
+        match object.keys().find(|key| !matches!(key.as_str(), "$if" | "$then" | "$else")) {
+            Some(key) => Err(Error::syntax("$if", format!("Unexpected key {:?}", key))),
+            None => Ok(())
+        }
+    }
+
+    /// Compile a string. The template string `Deserializer::OMIT` omits its value.
+    /// Escapes are only removed from template text. Data is kept as it is, apart from its placeholders.
+    pub(crate) fn string(string: &str, syntax: Syntax, origin: Origin) -> Result<Self> {
+        if origin == Origin::Template && string == Deserializer::OMIT {
+            return Ok(Self::Omit)
+        }
+        let text = |text: &str, before_placeholder: bool| match origin {
+            Origin::Template => syntax.unescape_text(text, before_placeholder),
+            _ => text.to_string()
+        };
+        let fragments = syntax.fragments(string)?;
+        if fragments.iter().all(|fragment| matches!(fragment, Fragment::Text(_))) {
+            return Ok(Self::Literal(Value::String(text(string, false))))
+        }
+        let fragments = match <[Fragment; 1]>::try_from(fragments) {
+            Ok([Fragment::Placeholder(placeholder)]) => return Ok(Self::Placeholder(Box::new(Expression::new(placeholder, origin)?))),
+            Ok(fragments) => Vec::from(fragments),
+            Err(fragments) => fragments
+        };
+        let mut fragments = fragments.into_iter().peekable();
+        let mut parts = Vec::new();
+        while let Some(fragment) = fragments.next() {
+            let part = match fragment {
+                Fragment::Text(fragment) => Part::Text(text(&fragment, fragments.peek().is_some())),
+                Fragment::Placeholder(placeholder) => Part::Placeholder(Box::new(Expression::new(placeholder, origin)?))
+            };
+            parts.push(part);
+        }
+        Ok(Self::Text { source: string.to_string(), parts })
+    }
+}
+
+impl Loop {
+    /// Compile `{"$for": "item in {items}", "$do": value}`.
+    pub(crate) fn compile(object: &Map<String, Value>, syntax: Syntax, origin: Origin) -> Result<Self> {
+        let (expression, variable, items) = Self::split(object)?;
+        Ok(Self {
+            expression: expression.to_string(),
+            variable: variable.to_string(),
+            items: Node::string(items, syntax, origin)?,
+            body: Node::compile(object.get("$do").unwrap_or(&Value::Null), syntax, origin)?
+        })
+    }
+
+    /// Split `{"$for": "item in {items}"}` into its expression, its variable and its items, without compiling them.
+    pub(crate) fn split(object: &Map<String, Value>) -> Result<(&str, &str, &str)> {
+        if let Some(key) = object.keys().find(|key| !matches!(key.as_str(), "$for" | "$do")) {
+            return Err(Error::syntax("$for", format!("Unexpected key {:?}", key)));
+        }
+        let expression = object["$for"]
+            .as_str()
+            .ok_or_else(|| Error::syntax("$for", "Expected a string like \"item in {items}\""))?;
+        let (variable, items) = expression
+            .split_once(" in ")
+            .map(|(variable, items)| (variable.trim(), items.trim()))
+            .filter(|(variable, _)| !variable.is_empty() && variable.chars().all(|character| character.is_alphanumeric() || character == '_'))
+            .ok_or_else(|| Error::syntax(expression, "Expected \"item in {items}\""))?;
+        Ok((expression, variable, items))
+    }
+}
+
+impl Expression {
+    /// Compile a placeholder.
+    pub(crate) fn new(placeholder: Placeholder, origin: Origin) -> Result<Self> {
+        let key = placeholder.inner().to_string();
+        let path = placeholder.path().str().to_string();
+        let segments = if placeholder.type_.is_none() {
+            Segment::compile(&placeholder.path(), origin)?
+        } else {
+            Vec::new()
+        };
+        let fallback = placeholder.fallback().map(|fallback| Fallback::compile(fallback, placeholder.syntax(), origin)).transpose()?;
+        let filters = placeholder.filters().into_iter().map(String::from).collect();
+        Ok(Self { placeholder, key, path, segments, fallback, filters })
+    }
+}
+
+impl Fallback {
+    /// Compile a fallback, which can be a JSON literal, a template or plain text.
+    fn compile(fallback: &str, syntax: Syntax, origin: Origin) -> Result<Self> {
+        match serde_json::from_str(fallback) {
+            Ok(value) => Ok(Self::Literal(value)),
+            Err(_) => Node::string(fallback, syntax, origin).map(Self::Template)
+        }
+    }
+}
+
+impl Segment {
+    /// Compile the segments of a path.
+    pub(crate) fn compile(path: &Path, origin: Origin) -> Result<Vec<Self>> {
+        path
+            .segments()
+            .into_iter()
+            .map(|segment| match path.syntax().placeholder(segment) {
+                Some(placeholder) => Expression::new(placeholder, origin).map(|expression| Self::Placeholder(Box::new(expression))),
+                None => Ok(Self::Key(segment.to_string()))
+            })
+            .collect()
+    }
+}
@@ -1,77 +1,152 @@
 //! This module contains the trait implementations for the project.
 
-use std::path::PathBuf;
+use std::{borrow::Cow, path::PathBuf};
 
 use serde_json::Value;
 
-use crate::{Context, Deserializer, Path, Placeholder};
+use crate::{Context, Deserializer, Error, Format, Origin, Path, Result, Segment};
 
 /// This trait provides a method to get a value from a JSON object using a dot-separated path.
 pub trait GetDot {
-    /// Get a value from a JSON object using a dot-separated path, deserializing each segment if needed.
-    fn get_dot_deserializing(&self, path: Path, deserializer: &Deserializer, context: &Context) -> serde_json::Result<Value>;
+    /// Get a value from a JSON object using compiled path segments, resolving the value found if needed.
+    /// If `origin` is the template, the value is the document being resolved and its resolved nodes are reused.
+    fn get_segments_deserializing(&self, path: &str, segments: &[Segment], deserializer: &Deserializer, context: &Context, origin: Origin) -> Result<Value>;
 }
 
 impl GetDot for Value {
-    fn get_dot_deserializing(&self, path: Path, deserializer: &Deserializer, context: &Context) -> serde_json::Result<Value> {
-        path
-            .segments()
-            .iter()
-            .fold(Ok(self.clone()), |acc, segment| {
-                if let Some(placeholder) = Placeholder::from_str(segment) {
-                    deserializer.resolve_placeholder(&placeholder, context)
+    fn get_segments_deserializing(&self, path: &str, segments: &[Segment], deserializer: &Deserializer, context: &Context, origin: Origin) -> Result<Value> {
+        let not_found = || Error::PathNotFound { path: path.to_string(), template_location: None };
+        // JSON pointer of the value found so far, while it's a node of the document.
+        let mut pointer = (origin == Origin::Template).then(String::new);
+        let mut resolved = origin == Origin::Resolved;
+        let mut value = Cow::Borrowed(self);
+        for segment in segments {
+            value = match segment {
+                Segment::Placeholder(expression) => {
+                    pointer = None;
+                    resolved = true;
+                    Cow::Owned(deserializer.render_expression(expression, context)?.ok_or_else(not_found)?)
+                },
+                Segment::Key(key) => {
+                    pointer = pointer.zip(get_token(&value, key)).map(|(pointer, token)| Path::pointer(&pointer, &token));
+                    let found = match value {
+                        Cow::Borrowed(value) => get_segment(value, key),
+                        Cow::Owned(value) => get_segment(&value, key).map(|value| Cow::Owned(value.into_owned()))
+                    };
+                    let found = found.ok_or_else(not_found)?;
+                    let needs_resolving = match found.as_ref() {
+                        Value::String(_) => true,
+                        Value::Object(object) => Deserializer::is_directive(object),
+                        _ => false
+                    };
+                    if needs_resolving && !resolved {
+                        resolved = true;
+                        Cow::Owned(deserializer.render_value(&found, origin, pointer.as_deref(), context)?.ok_or_else(not_found)?)
+                    } else {
+                        found
+                    }
+                }
+            };
+        }
+        if resolved || !matches!(value.as_ref(), Value::Array(_) | Value::Object(_)) {
+            return Ok(value.into_owned())
+        }
+        deserializer.render_value(&value, origin, pointer.as_deref(), context)?.ok_or_else(not_found)
+    }
+}
+
+/// Get a path segment from a value.
+/// Segments can be object keys, array indices like `0`, `[0]` or `[-1]`, or array slices like `[1:3]`.
+pub(crate) fn get_segment<'a>(value: &'a Value, segment: &str) -> Option<Cow<'a, Value>> {
+    if let Some(inner) = segment.strip_prefix('[').and_then(|segment| segment.strip_suffix(']')) {
+        let array = value.as_array()?;
+        if let Some((start, end)) = inner.split_once(':') {
+            let bound = |bound: &str, default: usize| -> Option<usize> {
+                if bound.trim().is_empty() {
+                    Some(default)
                 } else {
-                    acc?.get(segment)
-                        .ok_or_else(|| serde::de::Error::custom(format!("Path not found: {}", path.str())))
-                        .and_then(|value| {
-                            if let Some(value) = value.as_str() {
-                                deserializer.resolve_string(value, context)
-                            } else {
-                                Ok(value.clone())
-                            }
-                        })
+                    let bound = bound.trim().parse::<i64>().ok()?;
+                    let bound = if bound < 0 { array.len() as i64 + bound } else { bound };
+                    Some(bound.clamp(0, array.len() as i64) as usize)
                 }
-            })
+            };
+            let start = bound(start, 0)?;
+            let end = bound(end, array.len())?.max(start);
+            Some(Cow::Owned(Value::Array(array[start .. end].to_vec())))
+        } else {
+            get_index(array, inner.trim())
+        }
+    } else {
+        match value {
+            Value::Array(array) => get_index(array, segment),
+            value => value.get(segment).map(Cow::Borrowed)
+        }
+    }
+}
+
+/// Get the JSON pointer token of a path segment, if it refers to a single element.
+fn get_token(value: &Value, segment: &str) -> Option<String> {
+    match value {
+        Value::Array(array) => {
+            let index = segment.strip_prefix('[').and_then(|segment| segment.strip_suffix(']')).unwrap_or(segment);
+            let index = index.trim().parse::<i64>().ok()?;
+            let index = if index < 0 { array.len() as i64 + index } else { index };
+            Some(index.to_string())
+        },
+        Value::Object(_) => Some(segment.to_string()),
+        _ => None
     }
 }
 
+/// Get an array element by index, counting from the end if negative.
+fn get_index<'a>(array: &'a [Value], index: &str) -> Option<Cow<'a, Value>> {
+    let index = index.parse::<i64>().ok()?;
+    let index = if index < 0 { array.len() as i64 + index } else { index };
+    usize::try_from(index).ok().and_then(|index| array.get(index)).map(Cow::Borrowed)
+}
+
 /// Trait to convert to a JSON string.
 pub trait ToDeserializable {
-    /// Convert to a JSON string.
-    fn to_deserializable(&self) -> serde_json::Result<(Option<PathBuf>, Value)>;
+    /// Convert to a JSON string, along with the path of the file it was read from, if any.
+    /// Files are read from the context file system.
+    fn to_deserializable(&self, deserializer: &Deserializer, context: &Context) -> Result<(Option<PathBuf>, Value)>;
 }
 
 impl ToDeserializable for std::path::PathBuf {
-    fn to_deserializable(&self) -> serde_json::Result<(Option<PathBuf>, Value)> {
-        self.as_path().to_deserializable()
+    fn to_deserializable(&self, deserializer: &Deserializer, context: &Context) -> Result<(Option<PathBuf>, Value)> {
+        self.as_path().to_deserializable(deserializer, context)
     }
 }
 
 impl ToDeserializable for std::path::Path {
-    fn to_deserializable(&self) -> serde_json::Result<(Option<PathBuf>, Value)> {
-        let value = std::fs::read_to_string(self)
-            .map_err(|e| serde::de::Error::custom(format!("{} - {}", e, self.display())))?;
-        let value = serde_json::from_str(&value)?;
-        Ok((self.parent().map(|path| path.to_path_buf()), value))
+    fn to_deserializable(&self, deserializer: &Deserializer, context: &Context) -> Result<(Option<PathBuf>, Value)> {
+        (self, Format::from_path(self)).to_deserializable(deserializer, context)
+    }
+}
+
+impl ToDeserializable for (&std::path::Path, Format) {
+    fn to_deserializable(&self, deserializer: &Deserializer, context: &Context) -> Result<(Option<PathBuf>, Value)> {
+        let (path, format) = self;
+        let value = format.lenient(deserializer.lenient()).read(path, context.file_system())?;
+        Ok((Some(path.to_path_buf()), value))
     }
 }
 
 impl ToDeserializable for String {
-    fn to_deserializable(&self) -> serde_json::Result<(Option<PathBuf>, Value)> {
-        let value = serde_json::from_str(self)?;
-        Ok((None, value))
+    fn to_deserializable(&self, deserializer: &Deserializer, context: &Context) -> Result<(Option<PathBuf>, Value)> {
+        self.as_str().to_deserializable(deserializer, context)
     }
 }
 
 impl ToDeserializable for &str {
-    fn to_deserializable(&self) -> serde_json::Result<(Option<PathBuf>, Value)> {
-        let value = serde_json::from_str(self)?;
+    fn to_deserializable(&self, deserializer: &Deserializer, _context: &Context) -> Result<(Option<PathBuf>, Value)> {
+        let value = Format::Json.lenient(deserializer.lenient()).parse(self)?;
         Ok((None, value))
     }
 }
 
 impl ToDeserializable for Value {
-    fn to_deserializable(&self) -> serde_json::Result<(Option<PathBuf>, Value)> {
+    fn to_deserializable(&self, _deserializer: &Deserializer, _context: &Context) -> Result<(Option<PathBuf>, Value)> {
         Ok((None, self.clone()))
     }
 }
\ No newline at end of file
@@ -91,6 +91,42 @@ fn path_segments() {
     let path = Path::new("data");
     let segments = path.segments();
     assert_eq!(segments.len(), 1);
+
+    assert_eq!(Path::new("servers.0.host").segments(), ["servers", "0", "host"]);
+    assert_eq!(Path::new("servers[0].host").segments(), ["servers", "[0]", "host"]);
+    assert_eq!(Path::new("servers[-1][1:3]").segments(), ["servers", "[-1]", "[1:3]"]);
+    assert_eq!(Path::new("{file:a.json}[0]").segments(), ["{file:a.json}", "[0]"]);
+}
+
+#[test]
+fn array_paths() {
+    let context = Context::new().with_data(serde_json::json!({
+        "servers": [
+            { "host": "a", "port": 1 },
+            { "host": "b", "port": 2 },
+            { "host": "c", "port": 3 }
+        ]
+    }));
+    let json = serde_json::json!({
+        "first": "{servers.0.host}",
+        "second": "{servers[1].host}",
+        "last": "{servers.-1.host}",
+        "last_port": "{servers[-1].port}",
+        "middle": "{servers[1:3]}",
+        "head": "{servers[:1]}",
+        "url": "http://{servers[2].host}:{servers.2.port}"
+    });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(json, &context).expect("Failed to deserialize.");
+    assert_eq!(data["first"], "a");
+    assert_eq!(data["second"], "b");
+    assert_eq!(data["last"], "c");
+    assert_eq!(data["last_port"], 3);
+    assert_eq!(data["middle"], serde_json::json!([{ "host": "b", "port": 2 }, { "host": "c", "port": 3 }]));
+    assert_eq!(data["head"], serde_json::json!([{ "host": "a", "port": 1 }]));
+    assert_eq!(data["url"], "http://c:3");
+
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{servers.3.host}"), &context).unwrap_err();
+    assert!(matches!(error, Error::PathNotFound { .. }));
 }
 
 #[test]
@@ -106,9 +142,9 @@ fn custom_function() {
             .path()
             .str()
             .parse::<u64>()
-            .map_err(|e| serde::de::Error::custom(e))?;
+            .map_err(Error::custom)?;
         let duration = std::time::Duration::from_secs(seconds);
-        serde_json::to_value(&duration)
+        Ok(serde_json::to_value(duration)?)
     });
     let data: Time = Deserializer::new().deserialize_with_context(value, &context).expect("Failed to deserialize");
     assert_eq!(data.duration, std::time::Duration::from_secs(5));
@@ -127,21 +163,24 @@ fn placeholders() {
     assert_eq!(placeholder.type_, None);
 
     let placeholders = "   {time}   {time:3}    ";
-    let placeholders = Placeholder::placeholders(placeholders);
+    let placeholders = Placeholder::placeholders(placeholders).expect("Failed to get placeholders.");
     assert_eq!(placeholders.len(), 2);
     assert_eq!(placeholders[0].value, "{time}");
     assert_eq!(placeholders[1].value, "{time:3}");
 
     let recursive_placeholders = "{time:{time:5}}  {time}";
-    let recursive_placeholders = Placeholder::placeholders(recursive_placeholders);
+    let recursive_placeholders = Placeholder::placeholders(recursive_placeholders).expect("Failed to get placeholders.");
     assert_eq!(recursive_placeholders.len(), 2);
     assert_eq!(recursive_placeholders[0].value, "{time:{time:5}}");
     assert_eq!(recursive_placeholders[1].value, "{time}");
 
     let placeholders_path = "{{file:data--self-referencing.json}.data}";
-    let placeholders_path = Placeholder::placeholders(placeholders_path);
+    let placeholders_path = Placeholder::placeholders(placeholders_path).expect("Failed to get placeholders.");
     assert_eq!(placeholders_path.len(), 1);
     assert_eq!(placeholders_path[0].value, "{{file:data--self-referencing.json}.data}");
+
+    let placeholder = Placeholder { value: "{time:5}".into(), type_: Some("time".into()), ..Default::default() };
+    assert_eq!(placeholder.path().str(), "5");
 }
 
 #[test]
@@ -152,4 +191,996 @@ fn compose_function() {
         name: "Danilo".into(),
         age: 36
     })
-}
\ No newline at end of file
+}
+
+#[test]
+fn errors() {
+    let deserializer = Deserializer::new();
+
+    let error = deserializer.deserialize::<serde_json::Value>(r#"{ "name": "{data.name}" }"#).unwrap_err();
+    assert!(matches!(error, Error::PathNotFound { ref path, template_location: Some(ref location) } if path == "data.name" && location == "{data.name}"));
+
+    let error = deserializer.deserialize::<serde_json::Value>(r#"{ "name": "{unknown:data}" }"#).unwrap_err();
+    assert!(matches!(error, Error::FunctionNotFound { ref name } if name == "unknown"));
+
+    let file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("missing.json");
+    let error = deserializer.deserialize::<serde_json::Value>(file.clone()).unwrap_err();
+    assert!(matches!(error, Error::Io { ref path, .. } if *path == file));
+
+    let error = deserializer.deserialize::<serde_json::Value>(r#"{ "data": "{file:data.json}" }"#).unwrap_err();
+    assert!(matches!(error, Error::FunctionFailed { ref name, .. } if name == "file"));
+
+    let error: serde_json::Error = deserializer.deserialize::<Person>(r#"{ "name": "Danilo" }"#).unwrap_err().into();
+    assert!(error.to_string().contains("missing field"));
+}
+
+
+#[test]
+fn malformed_templates() {
+    let deserializer = Deserializer::new();
+    let context = Context::new().with_data(serde_json::json!({ "user": { "name": "Danilo" } }));
+
+    let error = deserializer.deserialize_with_context::<serde_json::Value>(r#"{ "info": "{user.nmae} is here" }"#, &context).unwrap_err();
+    assert!(matches!(error, Error::PathNotFound { ref template_location, .. } if template_location.as_deref() == Some("{user.nmae} is here")));
+
+    for template in ["a } b", "{user.name", "{user.name}} is here", "{{user.name} is here"] {
+        let error = deserializer.deserialize_with_context::<serde_json::Value>(serde_json::json!({ "info": template }), &context).unwrap_err();
+        assert!(matches!(error, Error::Syntax { template: ref offending, .. } if offending == template));
+    }
+}
+
+#[test]
+fn cycles() {
+    let deserializer = Deserializer::new();
+
+    let error = deserializer.deserialize::<serde_json::Value>(r#"{ "a": "{b}", "b": "{a}" }"#).unwrap_err();
+    assert!(matches!(error, Error::Cycle { ref chain } if chain == &["a", "b", "a"]));
+
+    let error = deserializer.deserialize::<serde_json::Value>(r#"{ "a": "{b.c}", "b": { "c": "{d}" }, "d": "{a}" }"#).unwrap_err();
+    assert!(matches!(error, Error::Cycle { ref chain } if chain == &["a", "b.c", "d", "a"]));
+
+    let error = deserializer.deserialize::<serde_json::Value>(r#"{ "a": { "b": "{a}" } }"#).unwrap_err();
+    assert!(matches!(error, Error::Cycle { .. }));
+
+    let file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("data-cycle-a.json");
+    let error = deserializer.deserialize::<serde_json::Value>(file).unwrap_err();
+    assert!(matches!(error, Error::Cycle { ref chain } if chain.len() == 3 && chain[0].ends_with("data-cycle-a.json") && chain[2].ends_with("data-cycle-a.json")));
+}
+
+
+#[test]
+fn escaped_braces() {
+    let json = r#"{
+        "snippet": "fn main() \\{ println!(\"\\{\\}\", 1); \\}",
+        "template": "{snippet}",
+        "info": "Hello, \\{name\\} is {name}",
+        "name": "Danilo",
+        "path": "C:\\\\{name}"
+    }"#;
+    let data: serde_json::Value = Deserializer::new().deserialize(json).expect("Failed to deserialize.");
+    assert_eq!(data["snippet"], "fn main() { println!(\"{}\", 1); }");
+    assert_eq!(data["template"], "fn main() { println!(\"{}\", 1); }");
+    assert_eq!(data["info"], "Hello, {name} is Danilo");
+    assert_eq!(data["path"], "C:\\Danilo");
+
+    let text = "/users/{id} \\d+ \\{";
+    assert_eq!(Placeholder::escape(text), "/users/\\{id\\} \\d+ \\\\\\{");
+    assert_eq!(Placeholder::unescape(&Placeholder::escape(text)), text);
+    let data: String = Deserializer::new().deserialize(serde_json::json!(Placeholder::escape(text))).expect("Failed to deserialize.");
+    assert_eq!(data, text);
+
+    // Data and function values aren't unescaped, and placeholders in custom function values are resolved.
+    let context = Context::new()
+        .with_data(serde_json::json!({ "unc": "\\\\server\\share", "regex": "^\\{\\d+\\}$", "name": "Danilo" }))
+        .with_function("template", |_deserializer, _context, _placeholder| Ok(serde_json::json!({ "text": "{name}", "escaped": "\\{name\\}" })))
+        .with_function("resolved", |deserializer, context, _placeholder| deserializer.resolve_value(&serde_json::json!("{name}"), context));
+    let json = serde_json::json!({ "unc": "{unc}", "regex": "{regex}", "path": "{unc}\\dir", "template": "{template:}", "resolved": "{resolved:}" });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(json, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({
+        "unc": "\\\\server\\share",
+        "regex": "^\\{\\d+\\}$",
+        "path": "\\\\server\\share\\dir",
+        "template": { "text": "Danilo", "escaped": "\\{name\\}" },
+        "resolved": "Danilo"
+    }));
+}
+
+
+#[test]
+fn fallbacks() {
+    let context = Context::new().with_data(serde_json::json!({
+        "db": { "host": "localhost" },
+        "defaults": { "host": "127.0.0.1", "port": 5432 }
+    }));
+    let json = serde_json::json!({
+        "host": "{db.host ?? {defaults.host}}",
+        "port": "{db.port ?? 5432}",
+        "default_port": "{db.port ?? {defaults.port}}",
+        "user": "{db.user ?? admin}",
+        "options": "{db.options ?? {\"ssl\": true}}",
+        "url": "postgres://{db.host}:{db.port ?? 5432}",
+        "nested": "{db.replica ?? {db.backup ?? none}}",
+        "file": "{file:missing.json ?? []}"
+    });
+    let context = context.with_directory(Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")));
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(json, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({
+        "host": "localhost",
+        "port": 5432,
+        "default_port": 5432,
+        "user": "admin",
+        "options": { "ssl": true },
+        "url": "postgres://localhost:5432",
+        "nested": "none",
+        "file": []
+    }));
+
+    let placeholder = Placeholder::from_str("{env:PORT ?? 8080}").expect("Failed to create placeholder.");
+    assert_eq!(placeholder.path().str(), "PORT");
+    assert_eq!(placeholder.fallback(), Some("8080"));
+}
+
+
+#[test]
+fn filters() {
+    let context = Context::new()
+        .with_data(serde_json::json!({ "user": { "name": "  Danilo  ", "age": 36 } }))
+        .with_filter("double", |_deserializer, _context, value| {
+            value
+                .as_u64()
+                .map(|value| serde_json::json!(value * 2))
+                .ok_or_else(|| Error::custom("Not a number"))
+        });
+    let json = serde_json::json!({
+        "name": "{user.name | upper | trim}",
+        "lower": "Hi, {user.name | trim | lower}!",
+        "age": "{user.age | double}",
+        "age_str": "{user.age | double | string}",
+        "nickname": "{user.nickname ?? dan | upper}"
+    });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(json, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({
+        "name": "DANILO",
+        "lower": "Hi, danilo!",
+        "age": 72,
+        "age_str": "72",
+        "nickname": "DAN"
+    }));
+
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{user.name | unknown}"), &context).unwrap_err();
+    assert!(matches!(error, Error::FunctionNotFound { ref name } if name == "unknown"));
+
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{user.name | double}"), &context).unwrap_err();
+    assert!(matches!(error, Error::FunctionFailed { ref name, .. } if name == "double"));
+}
+
+
+#[test]
+fn conditionals() {
+    let context = Context::new().with_data(serde_json::json!({
+        "features": { "metrics": true, "tracing": false },
+        "env": "production"
+    }));
+    let json = serde_json::json!({
+        "metrics": {
+            "$if": "{features.metrics}",
+            "$then": { "port": 9090 },
+            "$else": null
+        },
+        "tracing": {
+            "$if": "{features.tracing}",
+            "$then": { "endpoint": "http://jaeger" }
+        },
+        "log_level": {
+            "$if": "{features.tracing}",
+            "$then": "debug",
+            "$else": "info"
+        },
+        "debug": "{features.debug ?? $omit}",
+        "plugins": [
+            "core",
+            { "$if": "{features.tracing}", "$then": "tracing" },
+            { "$if": "{env}", "$then": "{env}" }
+        ],
+        "metrics_port": "{metrics.port}"
+    });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(json, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({
+        "metrics": { "port": 9090 },
+        "log_level": "info",
+        "plugins": ["core", "production"],
+        "metrics_port": 9090
+    }));
+
+    // Only templates omit values. Data and function values equal to "$omit" are kept.
+    let context = Context::new()
+        .with_data(serde_json::json!({ "marker": "$omit" }))
+        .with_function("marker", |_deserializer, _context, _placeholder| Ok(serde_json::json!("$omit")));
+    let json = serde_json::json!({
+        "data": "{marker}",
+        "function": "{marker:}",
+        "omitted": "$omit",
+        "fallback": "{omitted ?? 1}",
+        "list": ["{marker}", "$omit", "{missing ?? $omit}"]
+    });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(json, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({ "data": "$omit", "function": "$omit", "fallback": 1, "list": ["$omit"] }));
+
+    let error = Deserializer::new().deserialize::<serde_json::Value>(r#"{ "$if": true, "$than": 1 }"#).unwrap_err();
+    assert!(matches!(error, Error::Syntax { .. }));
+
+    for (value, truthy) in [(serde_json::json!(null), false), (serde_json::json!(0), false), (serde_json::json!(""), false), (serde_json::json!([]), false), (serde_json::json!("no"), true), (serde_json::json!(1.5), true)] {
+        assert_eq!(value.is_truthy(), truthy);
+    }
+}
+
+
+#[test]
+fn loops() {
+    let context = Context::new().with_data(serde_json::json!({
+        "services": [
+            { "name": "api", "host": "10.0.0.1", "port": 80 },
+            { "name": "db", "host": "10.0.0.2", "port": 5432 }
+        ],
+        "zones": ["a", "b"]
+    }));
+    let json = serde_json::json!({
+        "services": {
+            "$for": "svc in {services}",
+            "$do": {
+                "name": "{svc.name}",
+                "url": "http://{svc.host}:{svc.port}",
+                "index": "{$index}",
+                "first": "{$first}",
+                "last": "{$last}"
+            }
+        },
+        "hosts": [
+            "localhost",
+            { "$for": "svc in {services}", "$do": "{svc.host}" }
+        ],
+        "matrix": {
+            "$for": "zone in {zones}",
+            "$do": { "$for": "svc in {services}", "$do": "{svc.name}.{zone}" }
+        }
+    });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(json, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({
+        "services": [
+            { "name": "api", "url": "http://10.0.0.1:80", "index": 0, "first": true, "last": false },
+            { "name": "db", "url": "http://10.0.0.2:5432", "index": 1, "first": false, "last": true }
+        ],
+        "hosts": ["localhost", "10.0.0.1", "10.0.0.2"],
+        "matrix": [["api.a", "db.a"], ["api.b", "db.b"]]
+    }));
+
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!({ "$for": "{services}", "$do": 1 }), &context).unwrap_err();
+    assert!(matches!(error, Error::Syntax { .. }));
+}
+
+
+#[test]
+fn env_function() {
+    let environment = [("DATABASE_URL", "postgres://localhost"), ("PORT", "5432"), ("DEBUG", "true"), ("RATIO", "0.5"), ("TAGS", "[\"a\", \"b\"]")]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let context = Context::new().with_environment(Some(environment));
+    let json = serde_json::json!({
+        "url": "{env:DATABASE_URL}",
+        "port": "{env:int:PORT}",
+        "port_str": "{env:PORT}",
+        "debug": "{env:bool:DEBUG}",
+        "ratio": "{env:float:RATIO}",
+        "tags": "{env:json:TAGS}",
+        "workers": "{env:int:WORKERS ?? 8}",
+        "address": "0.0.0.0:{env:HTTP_PORT ?? 8080}",
+        "http_port": "{env:HTTP_PORT ?? 8080}",
+        "default_port": "{env:PORT ?? 8080}",
+        "ratio_default": "{env:float:RATIO_DEFAULT ?? {env:RATIO}}",
+        "optional": "{env:OPTIONAL ?? $omit}"
+    });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(json, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({
+        "url": "postgres://localhost",
+        "port": 5432,
+        "port_str": "5432",
+        "debug": true,
+        "ratio": 0.5,
+        "tags": ["a", "b"],
+        "workers": 8,
+        "address": "0.0.0.0:8080",
+        "http_port": "8080",
+        "default_port": "5432",
+        "ratio_default": 0.5
+    }));
+
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{env:int:DATABASE_URL}"), &context).unwrap_err();
+    assert!(matches!(error, Error::FunctionFailed { ref name, .. } if name == "env"));
+
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{env:MISSING}"), &context).unwrap_err();
+    assert!(matches!(error, Error::PathNotFound { ref path, .. } if path == "MISSING"));
+}
+
+
+#[test]
+#[cfg(all(feature = "yaml", feature = "toml", feature = "json5"))]
+fn file_formats() {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let data: serde_json::Value = Deserializer::new().deserialize(directory.join("data-formats.json")).expect("Failed to deserialize.");
+    let person = serde_json::json!({ "name": "Danilo", "age": 36 });
+    assert_eq!(data, serde_json::json!({ "yaml": person, "toml": person, "json5": person, "explicit": person }));
+
+    let data: Person = Deserializer::new().deserialize(directory.join("data-from-file.yaml")).expect("Failed to deserialize.");
+    assert_eq!(data, Person { name: "Danilo".into(), age: 36 });
+
+    let file_system = MemoryFileSystem::new()
+        .with_file("/broken.yaml", "name: Danilo\nage: [36\n")
+        .with_file("/broken.toml", "name = \"Danilo\"\nage = = 36\n")
+        .with_file("/broken.json5", "{\n  name: 'Danilo',\n  age: ]\n}");
+    let context = Context::new().with_file_system(file_system);
+    for (file, line) in [("/broken.yaml", 3), ("/broken.toml", 2), ("/broken.json5", 3)] {
+        let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(PathBuf::from(file), &context).unwrap_err();
+        match error {
+            Error::Parse { path: Some(path), location: Some((error_line, _)), .. } => assert_eq!((path, error_line), (PathBuf::from(file), line)),
+            error => panic!("Unexpected error: {}", error)
+        }
+    }
+}
+
+#[test]
+#[cfg(not(feature = "yaml"))]
+fn file_formats_without_features() {
+    let file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("data.yaml");
+    let error = Deserializer::new().deserialize::<serde_json::Value>(file).unwrap_err();
+    assert!(matches!(error, Error::Parse { .. }));
+    assert!(error.to_string().contains("`yaml` feature"));
+}
+
+
+#[test]
+fn lenient_json() {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let expected = serde_json::json!({
+        "data": { "name": "Danilo", "age": 36 },
+        "name": "Danilo",
+        "age": 36,
+        "url": "http://example.com/a//b",
+        "list": [1, 2, 3]
+    });
+
+    let data: serde_json::Value = Deserializer::new().deserialize(directory.join("data-lenient.jsonc")).expect("Failed to deserialize.");
+    assert_eq!(data, expected);
+
+    let text = std::fs::read_to_string(directory.join("data-lenient.jsonc")).expect("Failed to read file.");
+    let context = Context::new().with_directory(Some(directory));
+    assert!(Deserializer::new().deserialize_with_context::<serde_json::Value>(text.as_str(), &context).is_err());
+    let data: serde_json::Value = Deserializer::new().with_lenient(true).deserialize_with_context(text.as_str(), &context).expect("Failed to deserialize.");
+    assert_eq!(data, expected);
+
+    let text = "{\n  // Comment.\n  \"a\": 1,\n  \"b\": ]\n}";
+    let error = Deserializer::new().with_lenient(true).deserialize::<serde_json::Value>(text).unwrap_err();
+    match error {
+        Error::Parse { source, location, .. } => assert_eq!((source.line(), source.column(), location), (4, 8, Some((4, 8)))),
+        error => panic!("Unexpected error: {}", error)
+    }
+}
+
+
+#[test]
+fn send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Context>();
+    assert_send_sync::<Functions>();
+    assert_send_sync::<Deserializer>();
+    assert_send_sync::<Error>();
+
+    let context = std::sync::Arc::new(Context::new()
+        .with_data(serde_json::json!({ "greeting": "Hello" }))
+        .with_function("double", |_deserializer, _context, placeholder| {
+            let number = placeholder.path().str().parse::<u64>().map_err(Error::custom)?;
+            Ok(serde_json::json!(number * 2))
+        }));
+    std::thread::scope(|scope| {
+        let handles = (0 .. 4u64)
+            .map(|index| {
+                let context = context.clone();
+                scope.spawn(move || {
+                    let template = serde_json::json!({
+                        "text": format!("{{greeting}}, {}!", index),
+                        "double": format!("{{double:{}}}", index),
+                        "items": { "$for": "item in {list}", "$do": "{item}{text}" },
+                        "list": [1, 2, 3]
+                    });
+                    (0 .. 100).map(|_| Deserializer::new().deserialize_with_context::<serde_json::Value>(template.clone(), &context).expect("Failed to deserialize.")).collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        for (index, handle) in handles.into_iter().enumerate() {
+            let data = handle.join().expect("Failed to join thread.");
+            let text = format!("Hello, {}!", index);
+            let items = (1 .. 4).map(|item| format!("{}{}", item, text)).collect::<Vec<_>>();
+            let expected = serde_json::json!({ "text": text, "double": index * 2, "items": items, "list": [1, 2, 3] });
+            assert!(data.iter().all(|data| *data == expected));
+        }
+    });
+}
+
+#[test]
+fn local_functions() {
+    let counter = std::rc::Rc::new(std::cell::Cell::new(0));
+    let calls = counter.clone();
+    let local = LocalFunctions::new()
+        .with_function("count", move |_deserializer, _context, _placeholder| {
+            calls.set(calls.get() + 1);
+            Ok(serde_json::json!(calls.get()))
+        })
+        .with_filter("twice", |_deserializer, _context, value| Ok(serde_json::json!(value.as_u64().unwrap_or_default() * 2)));
+    let context = Context::new();
+    let data: Vec<u64> = local.scope(|| Deserializer::new().deserialize_with_context(serde_json::json!(["{count:}", "{count: | twice}"]), &context)).expect("Failed to deserialize.");
+    assert_eq!(data, [1, 4]);
+    assert_eq!(counter.get(), 2);
+
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{count:}"), &context).unwrap_err();
+    assert!(matches!(error, Error::FunctionNotFound { ref name } if name == "count"), "{error:?}");
+
+    let context = std::sync::Arc::new(context);
+    let error = local.scope(|| std::thread::scope(|scope| {
+        scope.spawn(|| Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{count:}"), &context).unwrap_err()).join().expect("Failed to join thread.")
+    }));
+    assert!(matches!(error, Error::FunctionNotFound { ref name } if name == "count"), "{error:?}");
+    assert_eq!(counter.get(), 2);
+}
+
+#[test]
+fn precompiled_templates() {
+    let value = serde_json::json!({
+        "name": "{user.name | upper}",
+        "greeting": "Hello, {user.name}! \\{escaped\\}",
+        "port": "{user.port ?? 8080}",
+        "first": "{user.tags[0]}",
+        "self": "{name}",
+        "tags": { "$for": "tag in {user.tags}", "$do": "#{tag}" },
+        "static": [1, "text"]
+    });
+    let template = Template::compile(value.clone()).expect("Failed to compile.");
+    for (name, port) in [("ana", None), ("bob", Some(9000))] {
+        let context = Context::new().with_data(serde_json::json!({ "user": { "name": name, "port": port, "tags": ["a", "b"] } }));
+        let data = template.render(&context).expect("Failed to render.");
+        let expected: serde_json::Value = Deserializer::new().deserialize_with_context(value.clone(), &context).expect("Failed to deserialize.");
+        assert_eq!(data, expected);
+        assert_eq!(data["name"], name.to_uppercase());
+        assert_eq!(data["greeting"], format!("Hello, {}! {{escaped}}", name));
+        assert_eq!(data["self"], name.to_uppercase());
+        assert_eq!(data["tags"], serde_json::json!(["#a", "#b"]));
+    }
+    let context = Context::new().with_data(serde_json::json!({ "user": { "name": "ana", "tags": [] } }));
+    let error = template.render(&context).unwrap_err();
+    assert!(matches!(error, Error::PathNotFound { ref path, .. } if path == "user.tags[0]"));
+
+    let file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("data-from-file.json");
+    let template = Deserializer::new().compile(file.clone()).expect("Failed to compile.");
+    let context = Context::new().with_data(serde_json::json!({ "data": { "time": "now" } }));
+    let data: Data = template.deserialize(&context).expect("Failed to deserialize.");
+    assert_eq!(data, Deserializer::new().deserialize_with_context(file, &context).expect("Failed to deserialize."));
+
+    assert!(matches!(Template::compile("{\"a\": \"{b\"}"), Err(Error::Syntax { .. })));
+    assert!(matches!(Template::compile(serde_json::json!({ "a": { "$for": "{items}", "$do": 1 } })), Err(Error::Syntax { .. })));
+    assert!(matches!(Template::compile(serde_json::json!({ "a": { "$if": true, "$do": 1 } })), Err(Error::Syntax { .. })));
+}
+
+#[test]
+fn memoized_nodes() {
+    let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = calls.clone();
+    let context = Context::new().with_function("count", move |_deserializer, _context, _placeholder| {
+        Ok(serde_json::json!(counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1))
+    });
+    let value = serde_json::json!({
+        "a": "{count:}",
+        "b": "{a}",
+        "c": "{a} {b}",
+        "list": ["{b}", "{list.0}", "{list[-2]}"],
+        "nested": { "x": "{c}" },
+        "numbers": [1, 2],
+        "items": { "$for": "item in {numbers}", "$do": "{item}-{a}" }
+    });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(value.clone(), &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({
+        "a": 1,
+        "b": 1,
+        "c": "1 1",
+        "list": [1, 1, 1],
+        "nested": { "x": "1 1" },
+        "numbers": [1, 2],
+        "items": ["1-1", "2-1"]
+    }));
+    assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    // Nodes referenced from inside loops don't see the loop variables.
+    let scoped = serde_json::json!({ "name": "{x ?? none}", "list": { "$for": "x in {xs}", "$do": "{name}" }, "xs": [1, 2] });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(scoped, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({ "name": "none", "list": ["none", "none"], "xs": [1, 2] }));
+    let scoped = serde_json::json!({ "list": { "$for": "x in {xs}", "$do": "{id}" }, "id": "{count:}", "xs": [1, 2] });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(scoped, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({ "list": [2, 2], "id": 2, "xs": [1, 2] }));
+    assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+    // Each render resolves the nodes again.
+    let template = Template::compile(value).expect("Failed to compile.");
+    assert_eq!(template.render(&context).expect("Failed to render.")["c"], "3 3");
+    assert_eq!(template.render(&context).expect("Failed to render.")["c"], "4 4");
+
+    // Deep chains are resolved in linear time.
+    let mut chain = serde_json::Map::new();
+    chain.insert("key0".into(), serde_json::json!("{count:}"));
+    for index in 1 .. 500 {
+        chain.insert(format!("key{}", index), serde_json::json!(format!("{{key{}}}", index - 1)));
+    }
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(serde_json::Value::Object(chain), &context).expect("Failed to deserialize.");
+    assert_eq!(data["key499"], 5);
+    assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 5);
+}
+
+#[test]
+fn file_cache() {
+    let directory = std::env::temp_dir().join(format!("json-template-file-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).expect("Failed to create directory.");
+    let common = directory.join("common.json");
+    std::fs::write(&common, r#"{ "name": "{user}", "version": 1 }"#).expect("Failed to write file.");
+    let value = serde_json::json!({ "a": "{file:common.json}", "b": "{{file:common.json}.version}", "c": "{file:common.json}" });
+
+    let context = Context::new()
+        .with_directory(Some(directory.clone()))
+        .with_data(serde_json::json!({ "user": "ana" }))
+        .with_file_cache(Some(FileCache::new()));
+    let render = |context: &Context| -> serde_json::Value { Deserializer::new().deserialize_with_context(value.clone(), context).expect("Failed to deserialize.") };
+    assert_eq!(render(&context), serde_json::json!({ "a": { "name": "ana", "version": 1 }, "b": 1, "c": { "name": "ana", "version": 1 } }));
+    let cache = context.file_cache().expect("File cache not set.");
+    let canonical = std::fs::canonicalize(&common).expect("Failed to canonicalize.");
+    assert_eq!(cache.paths(), vec![canonical.clone()]);
+    assert_eq!(cache.parsed(&common), Some(serde_json::json!({ "name": "{user}", "version": 1 })));
+    assert_eq!(cache.rendered(&common), None);
+
+    // Parsed files are reused until they're removed, rendered files follow the data.
+    std::fs::write(&common, r#"{ "name": "{user}", "version": 2 }"#).expect("Failed to write file.");
+    let context = context.with_data(serde_json::json!({ "user": "bob" }));
+    assert_eq!(render(&context)["a"], serde_json::json!({ "name": "bob", "version": 1 }));
+    assert!(context.file_cache().expect("File cache not set.").remove(&common));
+    assert!(!context.file_cache().expect("File cache not set.").remove(&common));
+    assert_eq!(render(&context)["a"], serde_json::json!({ "name": "bob", "version": 2 }));
+
+    // Rendered files are reused even if the data changes.
+    let context = context.with_file_cache(Some(FileCache::new().with_render_cache(true)));
+    assert_eq!(render(&context)["a"]["name"], "bob");
+    let context = context.with_data(serde_json::json!({ "user": "ana" }));
+    assert_eq!(render(&context)["a"]["name"], "bob");
+    assert_eq!(context.file_cache().expect("File cache not set.").rendered(&canonical), Some(serde_json::json!({ "name": "bob", "version": 2 })));
+    context.file_cache().expect("File cache not set.").clear();
+    assert!(context.file_cache().expect("File cache not set.").paths().is_empty());
+    assert_eq!(render(&context)["a"]["name"], "ana");
+    std::fs::remove_dir_all(&directory).expect("Failed to remove directory.");
+
+    // Lookups are canonicalized through the file system the files were read from.
+    let context = Context::new()
+        .with_file_system(MemoryFileSystem::new().with_file("t/common.json", r#"{ "version": 1 }"#))
+        .with_directory(Some(PathBuf::from("t")))
+        .with_file_cache(Some(FileCache::new()));
+    Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{file:common.json}"), &context).expect("Failed to deserialize.");
+    let cache = context.file_cache().expect("File cache not set.");
+    assert_eq!(cache.parsed("t/x/../common.json"), Some(serde_json::json!({ "version": 1 })));
+    assert!(cache.remove("./t/common.json"));
+}
+
+#[test]
+fn nested_includes() {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("includes");
+    let data: serde_json::Value = Deserializer::new().deserialize(directory.join("main.json")).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({ "db": { "host": "localhost", "pool": { "size": 10 } } }));
+
+    let value = serde_json::json!({ "db": "{file:db/main.json}" });
+    let context = Context::new().with_directory(Some(directory.clone()));
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(value.clone(), &context).expect("Failed to deserialize.");
+    assert_eq!(data["db"]["pool"]["size"], 10);
+
+    let context = context.with_fixed_directory(true);
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(value, &context).expect("Failed to deserialize.");
+    assert_eq!(data["db"]["pool"]["size"], 1);
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(directory.join("main.json"), &Context::new().with_fixed_directory(true)).expect("Failed to deserialize.");
+    assert_eq!(data["db"]["pool"]["size"], 1);
+}
+
+#[test]
+fn include_directories() {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("includes");
+    let context = Context::new()
+        .with_directory(Some(directory.clone()))
+        .with_include_directories(vec![directory.join("common"), directory.join("db")]);
+    assert_eq!(context.include_directories(), [directory.join("common"), directory.join("db")]);
+    let value = serde_json::json!({
+        "shared": "{file:shared.json}",
+        "pool": "{file:pool.json}",
+        "missing": "{file:missing.json ?? null}"
+    });
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(value, &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({ "shared": { "shared": true }, "pool": { "size": 1 }, "missing": null }));
+
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!("{file:missing.json}"), &context).unwrap_err();
+    let locations = [directory.join("missing.json"), directory.join("common").join("missing.json"), directory.join("db").join("missing.json")];
+    assert!(matches!(error, Error::FileNotFound { ref path, locations: ref tried } if path == "missing.json" && tried == &locations));
+    assert!(error.to_string().contains(&locations[2].display().to_string()));
+}
+
+#[test]
+fn sandbox() {
+    let directory = std::env::temp_dir().join(format!("json-template-sandbox-{}", std::process::id()));
+    let root = directory.join("root");
+    std::fs::create_dir_all(root.join("nested")).expect("Failed to create directory.");
+    std::fs::write(root.join("public.json"), r#"{ "public": true }"#).expect("Failed to write file.");
+    std::fs::write(directory.join("secret.json"), r#"{ "secret": true }"#).expect("Failed to write file.");
+
+    let context = Context::new()
+        .with_directory(Some(root.join("nested")))
+        .with_sandbox(Some(Sandbox::new(vec![root.clone()])));
+    let render = |context: &Context, template: &str| Deserializer::new().deserialize_with_context::<serde_json::Value>(serde_json::json!(template), context);
+    assert_eq!(render(&context, "{file:../public.json}").expect("Failed to deserialize."), serde_json::json!({ "public": true }));
+    for template in ["{file:../../secret.json}", &format!("{{file:{}}}", directory.join("secret.json").display()), "{file:../../secret.json ?? null}"] {
+        let error = render(&context, template).unwrap_err();
+        assert!(matches!(error, Error::Sandbox { .. }), "{}", error);
+        assert!(error.to_string().contains("outside of the allowed directories"));
+    }
+    assert!(matches!(render(&context, "{file:../missing.json}").unwrap_err(), Error::FileNotFound { .. }));
+    assert!(render(&context.clone().with_sandbox(None), "{file:../../secret.json}").is_ok());
+
+    // Relative directories starting with `..` read the checked file, whatever the symbolic links policy.
+    let current = std::env::current_dir().expect("Missing current directory.");
+    let relative = current
+        .components()
+        .skip(1)
+        .map(|_| PathBuf::from(".."))
+        .chain(root.components().skip(1).map(|component| PathBuf::from(component.as_os_str())))
+        .collect::<PathBuf>();
+    for symlinks in [SymlinkPolicy::WithinRoots, SymlinkPolicy::Follow, SymlinkPolicy::Deny] {
+        let context = Context::new()
+            .with_directory(Some(relative.clone()))
+            .with_sandbox(Some(Sandbox::new(vec![root.clone()]).with_symlinks(symlinks)));
+        assert_eq!(render(&context, "{file:public.json}").expect("Failed to deserialize."), serde_json::json!({ "public": true }));
+    }
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(directory.join("secret.json"), root.join("secret.json")).expect("Failed to create symlink.");
+        std::os::unix::fs::symlink(root.join("public.json"), root.join("link.json")).expect("Failed to create symlink.");
+        let error = render(&context, "{file:../secret.json}").unwrap_err();
+        assert!(matches!(error, Error::Sandbox { .. }), "{}", error);
+        assert!(render(&context, "{file:../link.json}").is_ok());
+
+        // `..` after a symbolic link to a directory leaves from the link target, not from the link.
+        std::fs::create_dir_all(directory.join("outside").join("inner")).expect("Failed to create directory.");
+        std::fs::write(directory.join("outside").join("leak.json"), r#"{ "leak": true }"#).expect("Failed to write file.");
+        std::os::unix::fs::symlink(directory.join("outside").join("inner"), root.join("escape")).expect("Failed to create symlink.");
+        std::os::unix::fs::symlink(root.join("nested"), root.join("alias")).expect("Failed to create symlink.");
+        let error = render(&context, "{file:../escape/../leak.json}").unwrap_err();
+        assert!(matches!(error, Error::Sandbox { .. }), "{}", error);
+        assert_eq!(render(&context, "{file:../alias/../public.json}").expect("Failed to deserialize."), serde_json::json!({ "public": true }));
+
+        let context = context.clone().with_sandbox(Some(Sandbox::new(vec![root.clone()]).with_symlinks(SymlinkPolicy::Follow)));
+        assert_eq!(render(&context, "{file:../secret.json}").expect("Failed to deserialize."), serde_json::json!({ "secret": true }));
+        assert!(matches!(render(&context, "{file:../escape/../leak.json}").unwrap_err(), Error::FileNotFound { .. }));
+
+        let context = context.clone().with_sandbox(Some(Sandbox::new(vec![root.clone()]).with_symlinks(SymlinkPolicy::Deny)));
+        assert!(matches!(render(&context, "{file:../link.json}").unwrap_err(), Error::Sandbox { .. }));
+        assert!(matches!(render(&context, "{file:../escape/../leak.json}").unwrap_err(), Error::FileNotFound { .. }));
+        assert!(render(&context, "{file:../public.json}").is_ok());
+    }
+
+    std::fs::remove_dir_all(&directory).expect("Failed to remove directory.");
+}
+
+#[test]
+fn file_systems() {
+    let file_system = MemoryFileSystem::new()
+        .with_file("templates/main.json", r#"{ "db": "{file:db/main.json}", "port": "{db.port}" }"#)
+        .with_file("templates/db/main.json", r#"{ "port": 5432, "pool": "{file:../pool.json}" }"#)
+        .with_file("templates/pool.json", r#"{ "size": "{pool_size}" }"#);
+    let context = Context::new()
+        .with_file_system(file_system)
+        .with_data(serde_json::json!({ "pool_size": 10 }));
+    let data: serde_json::Value = Deserializer::new().deserialize_with_context(PathBuf::from("templates/main.json"), &context).expect("Failed to deserialize.");
+    assert_eq!(data, serde_json::json!({ "db": { "port": 5432, "pool": { "size": 10 } }, "port": 5432 }));
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(PathBuf::from("templates/missing.json"), &context).unwrap_err();
+    assert!(error.is_not_found());
+    let context = context.with_sandbox(Some(Sandbox::new(vec![PathBuf::from("templates/db")])));
+    let error = Deserializer::new().deserialize_with_context::<serde_json::Value>(PathBuf::from("templates/main.json"), &context).unwrap_err();
+    assert!(matches!(error, Error::Sandbox { .. }), "{}", error);
+
+    static FILES: EmbeddedFileSystem = EmbeddedFileSystem::new(&[
+        ("data-from-file.json", include_str!("data-from-file.json")),
+        ("data.json", include_str!("data.json"))
+    ]);
+    let context = Context::new()
+        .with_file_system(FILES)
+        .with_data(serde_json::json!({ "data": { "time": "now" } }));
+    let template = Deserializer::new().compile_with_context(PathBuf::from("data-from-file.json"), &context).expect("Failed to compile.");
+    let data: Data = template.deserialize(&context).expect("Failed to deserialize.");
+    assert_eq!(data.name, "Danilo");
+    assert_eq!(data.time.as_deref(), Some("now"));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli() {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_json-template"))
+        .arg(directory.join("data-from-file.json"))
+        .args(["--set", "data.time=now", "--set", "data.age=37"])
+        .output()
+        .expect("Failed to run.");
+    assert!(output.status.success());
+    let data: Data = serde_json::from_slice(&output.stdout).expect("Failed to parse output.");
+    assert_eq!(data, Data { name: "Danilo".into(), age: 37, info: "Danilo is 37 years old.".into(), age_str: "37".into(), time: Some("now".into()) });
+
+    let output_file = std::env::temp_dir().join(format!("json-template-cli-{}.json", std::process::id()));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_json-template"))
+        .arg(directory.join("includes").join("main.json"))
+        .arg(directory.join("data.json"))
+        .args(["--pretty", "-o"])
+        .arg(&output_file)
+        .output()
+        .expect("Failed to run.");
+    assert!(output.status.success());
+    let text = std::fs::read_to_string(&output_file).expect("Failed to read output.");
+    std::fs::remove_file(&output_file).expect("Failed to remove output.");
+    assert!(text.contains("\n  \"db\": {"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_json-template"))
+        .arg(directory.join("includes").join("main.json"))
+        .arg("--dir")
+        .arg(directory.join("includes"))
+        .output()
+        .expect("Failed to run.");
+    assert!(output.status.success());
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).expect("Failed to parse output.");
+    assert_eq!(value, serde_json::json!({ "db": { "host": "localhost", "pool": { "size": 1 } } }));
+
+    let template = std::env::temp_dir().join(format!("json-template-cli-{}-template.json", std::process::id()));
+    std::fs::write(&template, r#"{ "common": "{file:shared.json}" }"#).expect("Failed to write template.");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_json-template"))
+        .arg(&template)
+        .arg("--include-dir")
+        .arg(directory.join("includes").join("common"))
+        .output()
+        .expect("Failed to run.");
+    std::fs::remove_file(&template).expect("Failed to remove template.");
+    assert!(output.status.success());
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).expect("Failed to parse output.");
+    assert_eq!(value, serde_json::json!({ "common": { "shared": true } }));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_json-template"))
+        .arg(directory.join("missing.json"))
+        .output()
+        .expect("Failed to run.");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.json"));
+}
+
+#[test]
+fn render() {
+    let deserializer = Deserializer::new();
+    let context = Context::new().with_data(serde_json::json!({ "user": { "name": "Danilo" } }));
+    let value = deserializer.render(r#"{ "name": "{user.name}", "text": "\\{literal\\}", "skipped": "$omit" }"#, &context).expect("Failed to render.");
+    assert_eq!(value, serde_json::json!({ "name": "Danilo", "text": "{literal}" }));
+    assert_eq!(deserializer.render(serde_json::json!("{user}"), &context).expect("Failed to render."), serde_json::json!({ "name": "Danilo" }));
+
+    let file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("data-from-file.json");
+    let context = Context::new().with_data(serde_json::json!({ "data": { "time": "now" } }));
+    assert_eq!(deserializer.render(file.clone(), &context).expect("Failed to render."), deserializer.deserialize_with_context::<serde_json::Value>(file.clone(), &context).expect("Failed to deserialize."));
+
+    let mut compact = Vec::new();
+    deserializer.render_to_writer(serde_json::json!({ "a": [1, "{data.time}"] }), &context, &mut compact, false).expect("Failed to render.");
+    assert_eq!(String::from_utf8(compact).expect("Invalid UTF-8."), r#"{"a":[1,"now"]}"#);
+    let mut pretty = Vec::new();
+    deserializer.render_to_writer(serde_json::json!({ "a": "{data.time}" }), &context, &mut pretty, true).expect("Failed to render.");
+    assert_eq!(String::from_utf8(pretty).expect("Invalid UTF-8."), "{\n  \"a\": \"now\"\n}");
+    assert!(matches!(deserializer.render("{ \"a\": \"{missing}\" }", &context), Err(Error::PathNotFound { .. })));
+}
+
+#[test]
+fn analyze() {
+    let context = Context::new().with_data(serde_json::json!({ "user": { "name": "Danilo" }, "servers": [{ "host": "a" }] }));
+    let value = serde_json::json!({
+        "name": "{user.name}",
+        "greeting": "Hello, {user.name} from {user.city}!",
+        "unknown": "{unknown:value}",
+        "fallback": "{user.age ?? 36}",
+        "self": "{name}",
+        "escaped": "\\{user.missing\\}",
+        "malformed": "{user.name",
+        "hosts": { "$for": "server in {servers}", "$do": ["{server.host}", "{server.port}"] }
+    });
+    let analysis = Deserializer::new().analyze(value, &context).expect("Failed to analyze.");
+    assert!(!analysis.is_valid());
+    let placeholders = analysis
+        .placeholders
+        .iter()
+        .map(|report| (report.pointer.as_str(), report.placeholder.value.as_str(), report.is_resolved()))
+        .collect::<Vec<_>>();
+    assert_eq!(placeholders, [
+        ("/fallback", "{user.age ?? 36}", true),
+        ("/greeting", "{user.name}", true),
+        ("/greeting", "{user.city}", false),
+        ("/hosts/$for", "{servers}", true),
+        ("/hosts/$do/0", "{server.host}", true),
+        ("/hosts/$do/1", "{server.port}", true),
+        ("/name", "{user.name}", true),
+        ("/self", "{name}", true),
+        ("/unknown", "{unknown:value}", false)
+    ]);
+    let unknown = analysis.unresolved().find(|report| report.pointer == "/unknown").expect("Missing report.");
+    assert_eq!(unknown.placeholder.type_.as_deref(), Some("unknown"));
+    assert!(matches!(unknown.error, Some(Error::FunctionNotFound { ref name }) if name == "unknown"));
+    assert!(matches!(analysis.unresolved().next().and_then(|report| report.error.as_ref()), Some(Error::PathNotFound { path, .. }) if path == "user.city"));
+    assert_eq!(analysis.malformed.len(), 1);
+    assert_eq!(analysis.malformed[0].0, "/malformed");
+
+    let analysis = Deserializer::new().analyze(r#"{ "name": "{user.name}" }"#, &context).expect("Failed to analyze.");
+    assert!(analysis.is_valid());
+
+    // Nothing is rendered: functions aren't called and environment variables aren't read.
+    let context = context
+        .with_directory(Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")))
+        .with_environment(Some(Default::default()))
+        .with_function("fail", |_, _, _| panic!("Functions aren't called."));
+    let value = serde_json::json!({
+        "called": "{fail:{user.name}}",
+        "argument": "{fail:{user.city}}",
+        "env": "{env:MISSING}",
+        "file": "{file:data.json}",
+        "missing": "{file:missing.json}",
+        "filter": "{user.name | upper | unknown}"
+    });
+    let analysis = Deserializer::new().analyze(value, &context).expect("Failed to analyze.");
+    let unresolved = analysis.unresolved().map(|report| report.pointer.as_str()).collect::<Vec<_>>();
+    assert_eq!(unresolved, ["/argument", "/filter", "/missing"]);
+
+    // Replaced built-in functions are only looked up, and directives are validated.
+    let context = Context::new().with_function("file", |_, _, placeholder| Ok(serde_json::json!(placeholder.path().str())));
+    let analysis = Deserializer::new().analyze(serde_json::json!({ "file": "{file:anywhere.json}", "if": { "$if": true, "$than": 1 } }), &context).expect("Failed to analyze.");
+    assert!(analysis.placeholders.iter().all(PlaceholderReport::is_resolved));
+    assert!(matches!(analysis.malformed.as_slice(), [(pointer, Error::Syntax { .. })] if pointer == "/if"));
+}
+
+#[test]
+fn dependency_graph() {
+    let directory = std::fs::canonicalize("tests/includes").expect("Missing fixtures.");
+    let context = Context::new()
+        .with_directory(Some(directory.clone()))
+        .with_data(serde_json::json!({ "data": { "name": "Danilo", "age": 36 } }));
+    let value = serde_json::json!({
+        "age": "{data.age}",
+        "summary": "{data.name} is {age}",
+        "user": { "label": "{summary}", "home": "{env:string:HOME}" },
+        "config": "{file:main.json}"
+    });
+    let graph = Deserializer::new().dependencies(value, &context).expect("Failed to build the graph.");
+    let key = |path: &str| Dependency::Key { file: None, path: path.to_string() };
+    let age = Dependency::Data("data.age".to_string());
+    assert_eq!(graph.dependencies(&key("summary")).collect::<Vec<_>>(), [&key("age"), &Dependency::Data("data.name".to_string())]);
+    assert_eq!(graph.dependencies(&key("user.home")).collect::<Vec<_>>(), [&Dependency::Environment("HOME".to_string())]);
+    let main = Dependency::File(directory.join("main.json"));
+    let db = Dependency::File(directory.join("db/main.json"));
+    let pool = Dependency::File(directory.join("db/pool.json"));
+    assert_eq!(graph.dependencies(&key("config")).collect::<Vec<_>>(), [&main]);
+    assert!(graph.dependencies(&Dependency::Key { file: Some(directory.join("db/main.json")), path: "pool".to_string() }).any(|node| *node == pool));
+
+    let affected = graph.affected_by(&age);
+    assert_eq!(affected, [&key("age"), &key("summary"), &key("user.label")].into_iter().collect());
+    assert_eq!(graph.affected_by(&Dependency::Data("data".to_string())).len(), 3);
+    assert!(graph.affected_by(&pool).contains(&main));
+    assert!(graph.affected_by(&db).contains(&key("config")));
+
+    let order = graph.topological_order().expect("Failed to order.");
+    let position = |node: &Dependency| order.iter().position(|other| *other == node).expect("Missing node.");
+    assert!(position(&age) < position(&key("age")));
+    assert!(position(&key("age")) < position(&key("summary")));
+    assert!(position(&key("summary")) < position(&key("user.label")));
+    assert!(position(&pool) < position(&key("config")));
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph dependencies {"));
+    assert!(dot.contains("\"summary\" -> \"age\";"));
+    assert!(dot.contains("\"age\" -> \"data:data.age\";"));
+
+    let cycle = serde_json::json!({ "a": "{b}", "b": "{c}", "c": "{a}" });
+    let graph = Deserializer::new().dependencies(cycle, &context).expect("Failed to build the graph.");
+    assert!(matches!(graph.topological_order(), Err(Error::Cycle { chain }) if chain.len() == 4 && chain.first() == chain.last()));
+
+    // Functions and filters come from the registry.
+    let context = context
+        .clone()
+        .with_function("uuid", |_, _, _| Ok(serde_json::json!("id")))
+        .with_function("file", |_, _, _| Ok(serde_json::Value::Null))
+        .with_filter("slug", |_, _, value| Ok(value));
+    let value = serde_json::json!({ "id": "{uuid:v4 | slug | upper}", "config": "{file:main.json}", "name": "{missing ?? {data.name}}" });
+    let graph = Deserializer::new().dependencies(value, &context).expect("Failed to build the graph.");
+    let function = |name: &str| Dependency::Function(name.to_string());
+    assert_eq!(graph.dependencies(&key("id")).collect::<Vec<_>>(), [&function("slug"), &function("uuid")]);
+    assert_eq!(graph.dependencies(&key("config")).collect::<Vec<_>>(), [&function("file")]);
+    assert!(graph.dependencies(&key("name")).any(|node| *node == Dependency::Data("data.name".to_string())));
+    assert_eq!(graph.dependents(&function("slug")).collect::<Vec<_>>(), [&key("id")]);
+    let error = Deserializer::new().dependencies(r#"{ "a": "{b | unknown}" }"#, &context).unwrap_err();
+    assert!(matches!(error, Error::FunctionNotFound { ref name } if name == "unknown"));
+
+    // Included files that can't be read fail, unless they're missing.
+    let file_system = MemoryFileSystem::new().with_file("templates/broken.json", "{ \"a\": ").with_file("secret.json", "{}");
+    let context = Context::new().with_file_system(file_system).with_directory(Some(PathBuf::from("templates")));
+    let dependencies = |template: &str| Deserializer::new().dependencies(serde_json::json!({ "a": template }), &context);
+    assert!(matches!(dependencies("{file:broken.json}").unwrap_err(), Error::Parse { .. }));
+    assert!(dependencies("{file:missing.json}").is_ok());
+    let context = context.with_sandbox(Some(Sandbox::new(vec![PathBuf::from("templates")])));
+    let error = Deserializer::new().dependencies(serde_json::json!({ "a": "{file:../secret.json}" }), &context).unwrap_err();
+    assert!(matches!(error, Error::Sandbox { .. }), "{}", error);
+
+    // Long chains are ordered without recursion.
+    let chain = (0 .. 10_000).map(|index| (format!("k{}", index), serde_json::json!(format!("{{k{}}}", index + 1)))).collect::<serde_json::Map<_, _>>();
+    let graph = Deserializer::new().dependencies(serde_json::Value::Object(chain), &Context::new()).expect("Failed to build the graph.");
+    let order = graph.topological_order().expect("Failed to order.");
+    assert_eq!(order.first().map(|node| node.to_string()), Some("data:k10000".to_string()));
+}
+
+#[test]
+fn syntax() {
+    let context = Context::new().with_data(serde_json::json!({ "user": { "id": 7, "name": "Danilo" } }));
+
+    let dollar = Syntax::new("${", "}").expect("Invalid syntax.");
+    let deserializer = Deserializer::new().with_syntax(dollar);
+    let value = serde_json::json!({
+        "route": "/users/{id}",
+        "url": "/users/{id}/${user.id}",
+        "id": "${user.id}",
+        "log": "{asctime} ${user.name | upper} }",
+        "escaped": dollar.escape("${user.id}"),
+        "fallback": "${missing ?? {\"a\": {\"b\": [1]}}}",
+        "nested": "${missing ?? {${user.id}}}"
+    });
+    let value = deserializer.render(value, &context).expect("Failed to render.");
+    assert_eq!(value, serde_json::json!({
+        "route": "/users/{id}",
+        "url": "/users/{id}/7",
+        "id": 7,
+        "log": "{asctime} DANILO }",
+        "escaped": "${user.id}",
+        "fallback": { "a": { "b": [1] } },
+        "nested": "{7}"
+    }));
+    assert!(matches!(deserializer.render(r#"{ "name": "${user.name" }"#, &context), Err(Error::Syntax { .. })));
+
+    let mustache = Syntax::new("{{", "}}").expect("Invalid syntax.");
+    let value = Deserializer::new().with_syntax(mustache).render(r#"{ "name": "{{user.name}} {id}", "id": "{{string:user.id}}" }"#, &context).expect("Failed to render.");
+    assert_eq!(value, serde_json::json!({ "name": "Danilo {id}", "id": "7" }));
+
+    // Delimiters can come from runtime configuration.
+    let (open, close) = (String::from("<%"), String::from("%>"));
+    let erb = Syntax::new(&open, &close)
+        .and_then(|syntax| syntax.with_function_separator('#'))
+        .and_then(|syntax| syntax.with_path_separator('/'))
+        .expect("Invalid syntax.");
+    // Only full delimiters are escaped, and data is never unescaped.
+    let context = context.clone().with_additional_data(serde_json::json!({ "text": "a\\%b<c" }));
+    let value = serde_json::json!({ "price": "50\\% off", "text": "<%text%>", "escaped": "\\<%text%>", "path": "C:\\\\<%text%>" });
+    let value = Deserializer::new().with_syntax(erb).render(value, &context).expect("Failed to render.");
+    assert_eq!(value, serde_json::json!({ "price": "50\\% off", "text": "a\\%b<c", "escaped": "<%text%>", "path": "C:\\a\\%b<c" }));
+    assert_eq!(erb.escape("a\\%b<%c%>"), "a\\%b\\<%c\\%>");
+    let value = serde_json::json!({ "name": "<%user/name%>", "id": "<%string#user/id%>", "age": "<%user/age ?? 36%>", "text": "{user.name}: <%name%>" });
+    let value = Deserializer::new().with_syntax(erb).render(value, &context).expect("Failed to render.");
+    assert_eq!(value, serde_json::json!({ "name": "Danilo", "id": "7", "age": 36, "text": "{user.name}: Danilo" }));
+    assert_eq!(Path::new("servers/<%index%>/host[0]").with_syntax(erb).segments(), ["servers", "<%index%>", "host", "[0]"]);
+
+    assert!(Syntax::new("", "}").is_err());
+    assert!(Syntax::new("%", "%").is_err());
+    assert!(Syntax::new("<<<<<<<<<", ">").is_err());
+    for separator in ['|', '?', '[', '#', '%', ' '] {
+        assert!(erb.with_path_separator(separator).is_err(), "{:?}", separator);
+    }
+    assert!(erb.with_function_separator('/').is_err());
+}